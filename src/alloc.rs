@@ -0,0 +1,118 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+// Off until `--allocations` turns it on, so other runs only pay for one load per allocation
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+// Live bytes can go negative, freeing memory allocated before counting started
+static LIVE_BYTES: AtomicI64 = AtomicI64::new(0);
+static PEAK_LIVE_BYTES: AtomicI64 = AtomicI64::new(0);
+
+// The system allocator, counting what goes through it once enabled
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn allocated(size: usize) {
+        if ENABLED.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+            let live = LIVE_BYTES.fetch_add(size as i64, Ordering::Relaxed) + size as i64;
+            PEAK_LIVE_BYTES.fetch_max(live, Ordering::Relaxed);
+        }
+    }
+
+    fn freed(size: usize) {
+        if ENABLED.load(Ordering::Relaxed) {
+            LIVE_BYTES.fetch_sub(size as i64, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::freed(layout.size());
+    }
+
+    // A realloc counts as freeing the old block and allocating the new one, as it usually is
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::freed(layout.size());
+            Self::allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// What a run allocated. Every strategy runs in this process, so allocations by tasks that outlive a run count
+// against the next one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocationReport {
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    // The most heap in use during the run on top of what was in use when it started
+    pub peak_live_bytes: u64,
+}
+
+impl AllocationReport {
+    pub fn per_write(&self, writes: u64) -> f64 {
+        self.allocations as f64 / writes.max(1) as f64
+    }
+
+    pub fn bytes_per_write(&self, writes: u64) -> f64 {
+        self.allocated_bytes as f64 / writes.max(1) as f64
+    }
+}
+
+// Counts allocations between `start` and `finish`
+pub struct AllocationCounter {
+    allocations: u64,
+    allocated_bytes: u64,
+    live_bytes: i64,
+}
+
+impl AllocationCounter {
+    // None unless counting was enabled
+    pub fn start() -> Option<Self> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+        let live_bytes = LIVE_BYTES.load(Ordering::Relaxed);
+        PEAK_LIVE_BYTES.store(live_bytes, Ordering::Relaxed);
+        Some(Self {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            live_bytes,
+        })
+    }
+
+    pub fn finish(self) -> AllocationReport {
+        AllocationReport {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed) - self.allocations,
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed) - self.allocated_bytes,
+            peak_live_bytes: (PEAK_LIVE_BYTES.load(Ordering::Relaxed) - self.live_bytes).max(0) as u64,
+        }
+    }
+}
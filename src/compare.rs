@@ -0,0 +1,334 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::output;
+use crate::run_config::parse;
+
+pub struct CompareOptions {
+    pub baseline: PathBuf,
+    // Results to compare against the baseline instead of running the scenarios now
+    pub against: Option<PathBuf>,
+    // Percent a metric can get worse by before it counts as a regression
+    pub threshold: f64,
+    // Significance level the difference has to reach
+    pub alpha: f64,
+    // Names of the metrics that can fail the comparison, all of them if empty
+    pub gated: Vec<String>,
+    // Everything else, passed on to the run
+    pub run_args: Vec<String>,
+}
+
+impl CompareOptions {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut args = args;
+        let usage = || Error::argument("Usage: compare BASELINE [--against RESULTS] [--threshold PCT]");
+        let mut options = Self {
+            baseline: PathBuf::from(args.next().ok_or_else(usage)?),
+            against: None,
+            threshold: 5.0,
+            alpha: 0.05,
+            gated: Vec::new(),
+            run_args: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+            match arg.as_str() {
+                "--against" => options.against = Some(PathBuf::from(value()?)),
+                "--threshold" => options.threshold = parse(&value()?)?,
+                "--alpha" => options.alpha = parse(&value()?)?,
+                "--metrics" => options.gated = value()?.split(',').map(str::to_string).collect(),
+                _ => options.run_args.push(arg),
+            }
+        }
+        if let Some(unknown) = options.gated.iter().find(|name| !METRICS.iter().any(|metric| metric.name == *name)) {
+            let names = METRICS.iter().map(|m| m.name).collect::<Vec<_>>().join(", ");
+            return Err(Error::argument(format!("Unknown metric {}, expected one of {}", unknown, names)));
+        }
+        Ok(options)
+    }
+}
+
+pub struct Metric {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub higher_is_better: bool,
+    pub value: fn(&Value) -> Option<f64>,
+}
+
+pub const METRICS: &[Metric] = &[
+    Metric { name: "writes_per_s", label: "Writes/s", higher_is_better: true, value: |r| r["writes_per_s"].as_f64() },
+    Metric { name: "mb_per_s", label: "MB/s", higher_is_better: true, value: |r| r["mb_per_s"].as_f64() },
+    Metric { name: "mean", label: "Mean ms", higher_is_better: false, value: |r| latency_ms(r, "mean_ns") },
+    Metric { name: "p50", label: "P50 ms", higher_is_better: false, value: |r| latency_ms(r, "median_ns") },
+    Metric { name: "p90", label: "P90 ms", higher_is_better: false, value: |r| latency_ms(r, "p90_ns") },
+    Metric { name: "p99", label: "P99 ms", higher_is_better: false, value: |r| latency_ms(r, "p99_ns") },
+    Metric { name: "max", label: "Max ms", higher_is_better: false, value: |r| latency_ms(r, "max_ns") },
+    Metric {
+        name: "caller_p99",
+        label: "Caller P99 ms",
+        higher_is_better: false,
+        value: |r| Some(r["caller_latency"]["p99_ns"].as_f64()? / 1_000_000.0),
+    },
+    Metric {
+        name: "cpu_per_write",
+        label: "CPU us/write",
+        higher_is_better: false,
+        value: |r| r["resources"]["cpu_us_per_write"].as_f64(),
+    },
+];
+
+fn latency_ms(result: &Value, field: &str) -> Option<f64> {
+    Some(result["latency"][field].as_f64()? / 1_000_000.0)
+}
+
+// The results in a file written with --json, or in a --history file
+pub fn load(path: &Path) -> Result<Vec<Value>> {
+    let text = fs::read_to_string(path).map_err(Error::setup("reading the results"))?;
+    if let Ok(document) = serde_json::from_str::<Value>(&text) {
+        if let Some(results) = document["results"].as_array() {
+            return Ok(results.clone());
+        }
+    }
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut entry: Value = serde_json::from_str(line).map_err(|e| {
+                let message = format!("{} is neither a results document nor a history file: {}", path.display(), e);
+                Error::invalid("reading the results", message)
+            })?;
+            Ok(entry["result"].take())
+        })
+        .collect()
+}
+
+// Prints the change in every metric of every scenario found in both, and returns how many regressed
+pub fn compare(baseline: &[Value], current: &[Value], options: &CompareOptions) -> usize {
+    let baseline = by_scenario(baseline);
+    let current = by_scenario(current);
+    let mut regressions = 0;
+
+    for (scenario, current_runs) in &current {
+        let Some((_, baseline_runs)) = baseline.iter().find(|(name, _)| name == scenario) else {
+            println!("Only in the current results: {}", scenario);
+            continue;
+        };
+        println!("===== {} =====", scenario);
+        println!("  {} baseline runs, {} current", baseline_runs.len(), current_runs.len());
+        println!("  {:<14} {:>12} {:>12} {:>10} {:>10}", "Metric", "Baseline", "Current", "Change", "p-value");
+        for metric in METRICS {
+            let before: Vec<f64> = baseline_runs.iter().filter_map(|run| (metric.value)(run)).collect();
+            let after: Vec<f64> = current_runs.iter().filter_map(|run| (metric.value)(run)).collect();
+            if before.is_empty() || after.is_empty() {
+                continue;
+            }
+            let (before_median, after_median) = (median(&before), median(&after));
+            let change = change_percent(before_median, after_median);
+            let worse = change.map(|change| if metric.higher_is_better { -change } else { change });
+            let p_value = mann_whitney(&before, &after);
+
+            // With too few repetitions for the test to ever reach alpha, the threshold alone decides
+            let significant = p_value < options.alpha || min_p_value(before.len(), after.len()) >= options.alpha;
+            let gated = options.gated.is_empty() || options.gated.iter().any(|name| name == metric.name);
+            let regressed = gated && worse.is_some_and(|worse| worse > options.threshold) && significant;
+            regressions += regressed as usize;
+            println!(
+                "  {:<14} {:>12.2} {:>12.2} {:>10} {:>10.3}{}",
+                metric.label,
+                before_median,
+                after_median,
+                change.map_or("n/a".to_string(), |change| format!("{:.1}%", change)),
+                p_value,
+                if regressed { "  REGRESSION" } else { "" }
+            );
+        }
+        println!();
+    }
+    for (scenario, _) in baseline.iter().filter(|(name, _)| !current.iter().any(|(other, _)| other == name)) {
+        println!("Only in the baseline: {}", scenario);
+    }
+
+    if regressions == 0 {
+        println!("No metric regressed by more than {:.1}%", options.threshold);
+    } else {
+        println!("Metrics that regressed by more than {:.1}%: {}", options.threshold, regressions);
+    }
+    regressions
+}
+
+// Repetitions of the same scenario grouped together, in the order each first appears
+fn by_scenario(results: &[Value]) -> Vec<(String, Vec<&Value>)> {
+    let mut scenarios: Vec<(String, Vec<&Value>)> = Vec::new();
+    for result in results {
+        let name = output::scenario_name(result);
+        match scenarios.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, runs)) => runs.push(result),
+            None => scenarios.push((name, vec![result])),
+        }
+    }
+    scenarios
+}
+
+// Relative change in percent, None when the baseline is 0 and the current value isn't, e.g. a counter of
+// faults or context switches that was idle in the baseline
+fn change_percent(before: f64, after: f64) -> Option<f64> {
+    if before == after {
+        Some(0.0)
+    } else if before == 0.0 {
+        None
+    } else {
+        Some((after - before) / before * 100.0)
+    }
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => (sorted[middle - 1] + sorted[middle]) / 2.0,
+        _ => sorted[middle],
+    }
+}
+
+// Two sided p-value of the Mann-Whitney U test that both samples come from the same distribution. Exact for
+// small samples without ties, otherwise the normal approximation with a tie correction.
+fn mann_whitney(a: &[f64], b: &[f64]) -> f64 {
+    let (n1, n2) = (a.len(), b.len());
+    let mut pooled: Vec<(f64, bool)> = a.iter().map(|&v| (v, true)).chain(b.iter().map(|&v| (v, false))).collect();
+    pooled.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // Tied values share the average of their ranks
+    let mut rank_sum_a = 0.0;
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < pooled.len() {
+        let end = start + pooled[start..].iter().take_while(|(value, _)| *value == pooled[start].0).count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum_a += rank * pooled[start..end].iter().filter(|(_, from_a)| *from_a).count() as f64;
+        let tied = (end - start) as f64;
+        ties += tied * tied * tied - tied;
+        start = end;
+    }
+    let u_a = rank_sum_a - (n1 * (n1 + 1)) as f64 / 2.0;
+    let u = u_a.min((n1 * n2) as f64 - u_a);
+
+    if ties == 0.0 && n1 + n2 <= 40 {
+        let counts = u_distribution(n1, n2);
+        let total: f64 = counts.iter().sum();
+        let tail: f64 = counts[..=u as usize].iter().sum();
+        return (2.0 * tail / total).min(1.0);
+    }
+    let n = (n1 + n2) as f64;
+    let variance = (n1 * n2) as f64 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return 1.0;
+    }
+    let z = (u - (n1 * n2) as f64 / 2.0 + 0.5).min(0.0) / variance.sqrt();
+    (2.0 * normal_cdf(z)).min(1.0)
+}
+
+// How many of the ways to interleave n1 and n2 values give each U, from f(m, n, u) = f(m-1, n, u-n) + f(m, n-1, u)
+fn u_distribution(n1: usize, n2: usize) -> Vec<f64> {
+    let mut previous: Vec<Vec<f64>> = (0..=n2).map(|_| vec![1.0]).collect();
+    for m in 1..=n1 {
+        let mut row: Vec<Vec<f64>> = vec![vec![1.0]];
+        for n in 1..=n2 {
+            let counts = (0..=m * n)
+                .map(|u| {
+                    let fewer_a = if u >= n { previous[n].get(u - n).copied().unwrap_or(0.0) } else { 0.0 };
+                    fewer_a + row[n - 1].get(u).copied().unwrap_or(0.0)
+                })
+                .collect();
+            row.push(counts);
+        }
+        previous = row;
+    }
+    previous.pop().expect("Missing U distribution")
+}
+
+// The smallest p-value the exact test can give for these sample sizes, when one sample is entirely below the other
+fn min_p_value(n1: usize, n2: usize) -> f64 {
+    let orderings: f64 = (1..=n1).map(|i| (n2 + i) as f64 / i as f64).product();
+    (2.0 / orderings).min(1.0)
+}
+
+// Standard normal CDF, from the Abramowitz and Stegun 7.1.26 approximation of erf
+fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    if z >= 0.0 {
+        (1.0 + erf) / 2.0
+    } else {
+        (1.0 - erf) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn median_of_odd_and_even_lengths() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(median(&[7.0]), 7.0);
+    }
+
+    #[test]
+    fn change_against_a_zero_baseline() {
+        assert_eq!(change_percent(200.0, 250.0), Some(25.0));
+        assert_eq!(change_percent(200.0, 100.0), Some(-50.0));
+        assert_eq!(change_percent(0.0, 0.0), Some(0.0));
+        assert_eq!(change_percent(0.0, 3.0), None);
+    }
+
+    #[test]
+    fn exact_p_values() {
+        // Completely separated samples give 2 / C(n1 + n2, n1)
+        assert_close(mann_whitney(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]), 0.1, 1e-12);
+        assert_close(mann_whitney(&[6.0, 7.0, 8.0, 9.0, 10.0], &[1.0, 2.0, 3.0, 4.0, 5.0]), 2.0 / 252.0, 1e-12);
+        // U = 1 with 4 and 4 values, 2 of the 70 orderings are as extreme on each side
+        assert_close(mann_whitney(&[1.0, 2.0, 3.0, 5.0], &[4.0, 6.0, 7.0, 8.0]), 4.0 / 70.0, 1e-12);
+        // Perfectly interleaved samples are as alike as they can be
+        assert_close(mann_whitney(&[1.0, 4.0, 5.0, 8.0], &[2.0, 3.0, 6.0, 7.0]), 1.0, 1e-12);
+    }
+
+    #[test]
+    fn approximate_p_values() {
+        // Ties switch to the normal approximation, matching R's wilcox.test(c(1, 2, 2, 3), c(2, 3, 4, 5))
+        assert_close(mann_whitney(&[1.0, 2.0, 2.0, 3.0], &[2.0, 3.0, 4.0, 5.0]), 0.1367, 5e-4);
+        assert_eq!(mann_whitney(&[5.0; 10], &[5.0; 10]), 1.0);
+
+        // As do samples too large for the exact distribution, here 25 values each with 14 of a's above 1..14 of b's
+        let a: Vec<f64> = (0..25).map(f64::from).collect();
+        let b: Vec<f64> = (10..35).map(|value| f64::from(value) + 0.5).collect();
+        // U = 1 + 2 + ... + 14, sigma = sqrt(25 * 25 * 51 / 12)
+        let z = (105.0 - 312.5 + 0.5) / (25.0 * 25.0 * 51.0 / 12.0_f64).sqrt();
+        assert_close(mann_whitney(&a, &b), 2.0 * normal_cdf(z), 1e-12);
+        assert!(mann_whitney(&a, &b) < 1e-4);
+    }
+
+    #[test]
+    fn smallest_exact_p_value() {
+        assert_close(min_p_value(3, 3), 0.1, 1e-12);
+        assert_close(min_p_value(5, 5), 2.0 / 252.0, 1e-12);
+        assert_eq!(min_p_value(1, 1), 1.0);
+    }
+
+    #[test]
+    fn standard_normal_cdf() {
+        assert_close(normal_cdf(0.0), 0.5, 1e-7);
+        assert_close(normal_cdf(1.96), 0.975_002, 1e-6);
+        assert_close(normal_cdf(-1.96), 0.024_998, 1e-6);
+        assert_close(normal_cdf(-3.0), 0.001_350, 1e-6);
+    }
+}
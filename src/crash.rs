@@ -0,0 +1,352 @@
+use std::collections::HashSet;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::Builder;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::error::{Error, Result};
+use crate::load_test::LoadTest;
+use crate::queue::Capacity;
+use crate::record::{Frame, RecordReader};
+use crate::run_config::{parse, parse_range, Durability, RunConfig};
+use crate::storage::{GrowthPolicy, Storage};
+use crate::workload::MessageSource;
+
+// Outcome of killing one child run and recovering its file
+struct CrashRun {
+    acked: u64,
+    // Acked before the kill but missing or damaged in the recovered file
+    lost: u64,
+    torn_tail: bool,
+    // Undecodable stretches before the end of the file. A write killed part way through leaves one behind, and
+    // an acked record damaged by one is already counted as lost, so these don't fail the run.
+    corrupted: u64,
+}
+
+impl CrashRun {
+    fn passed(&self) -> bool {
+        self.lost == 0
+    }
+}
+
+// crash: runs every strategy in a child process, kills it with SIGKILL at a random point and checks
+// that every write it acked survived
+pub async fn run(tests: Vec<Box<dyn LoadTest>>, args: impl Iterator<Item = String>) -> Result<()> {
+    let mut runs = 5;
+    let mut kill_after = 200..2_000;
+    let mut levels = vec![Durability::Written, Durability::Synced];
+    let mut clients = 8;
+    let mut power_loss = false;
+    let mut target_dir = env::temp_dir();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--runs" => runs = parse(&value()?)?,
+            // Range of milliseconds to let the child run before killing it
+            "--kill-after" => kill_after = parse_range(&value()?)?,
+            "--durability" => levels = value()?.split(',').map(Durability::parse).collect::<Result<_>>()?,
+            "--clients" => clients = parse(&value()?)?,
+            "--power-loss" => power_loss = true,
+            "--target-dir" => target_dir = PathBuf::from(value()?),
+            _ => return Err(Error::argument(format!("Unknown argument {}", arg))),
+        }
+    }
+
+    let mut rng = StdRng::from_entropy();
+    let mut summary = Vec::new();
+    for test in &tests {
+        for &durability in &levels {
+            println!("===== Crash testing {} ({} durability, {} runs) =====", test.get_name(), durability, runs);
+            let mut results = Vec::new();
+            for _ in 0..runs {
+                let kill_after = Duration::from_millis(rng.gen_range(kill_after.clone()) as u64);
+                let result =
+                    crash_once(test.get_name(), durability, clients, kill_after, power_loss, &target_dir, &mut rng).await;
+                match &result {
+                    Ok(result) => println!(
+                        "  Killed after {:>5} ms: {} {} acked, {} lost, {} corrupted{}",
+                        kill_after.as_millis(),
+                        if result.passed() { "PASS" } else { "FAIL" },
+                        result.acked,
+                        result.lost,
+                        result.corrupted,
+                        if result.torn_tail { ", torn tail detected" } else { "" }
+                    ),
+                    Err(error) => println!("  Killed after {:>5} ms: FAIL {}", kill_after.as_millis(), error),
+                }
+                results.push(result);
+            }
+            println!();
+            summary.push((test.get_name().to_string(), durability, results));
+        }
+    }
+
+    println!("===== Crash test summary{} =====", if power_loss { " (simulated power loss)" } else { "" });
+    println!(
+        "  {:<70} {:<10} {:>6} {:>8} {:>10} {:>8} {:>6} {:>10} {:>7}",
+        "Strategy", "Durability", "Result", "Passed", "Acked", "Lost", "Torn", "Corrupted", "Errors"
+    );
+    for (name, durability, results) in summary {
+        // Runs that errored count as failed, the totals are over the ones that got as far as recovery
+        let recovered: Vec<&CrashRun> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+        let passed = recovered.iter().filter(|r| r.passed()).count();
+        println!(
+            "  {:<70} {:<10} {:>6} {:>8} {:>10} {:>8} {:>6} {:>10} {:>7}",
+            name,
+            durability.to_string(),
+            if passed == results.len() { "PASS" } else { "FAIL" },
+            format!("{}/{}", passed, results.len()),
+            recovered.iter().map(|r| r.acked).sum::<u64>(),
+            recovered.iter().map(|r| r.lost).sum::<u64>(),
+            recovered.iter().filter(|r| r.torn_tail).count(),
+            recovered.iter().map(|r| r.corrupted).sum::<u64>(),
+            results.len() - recovered.len(),
+        );
+    }
+    Ok(())
+}
+
+async fn crash_once(
+    name: &str,
+    durability: Durability,
+    clients: usize,
+    kill_after: Duration,
+    power_loss: bool,
+    target_dir: &Path,
+    rng: &mut StdRng,
+) -> Result<CrashRun> {
+    let dir = Builder::new()
+        .prefix("rusty-writes-")
+        .tempdir_in(target_dir)
+        .map_err(Error::setup("creating the temp directory"))?;
+    let file_path = dir.path().join("test_log");
+
+    let mut child = Command::new(env::current_exe().map_err(Error::setup("finding the current executable"))?)
+        .arg("crash-child")
+        .args(["--test", name, "--durability", &durability.to_string(), "--clients", &clients.to_string()])
+        .arg("--file")
+        .arg(&file_path)
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(Error::setup("starting the child"))?;
+
+    // The child prints the sequence number of every write once it's acked
+    let stdout = child.stdout.take().ok_or_else(|| Error::Child("no stdout to read".to_string()))?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut acked = HashSet::new();
+    let kill = sleep(kill_after);
+    tokio::pin!(kill);
+    loop {
+        tokio::select! {
+            _ = &mut kill => break,
+            line = lines.next_line() => match line.map_err(Error::io("reading the child's output"))? {
+                Some(line) => { acked.insert(sequence(&line)?); }
+                None => {
+                    let status = child.wait().await.map_err(Error::io("waiting for the child"))?;
+                    return Err(Error::Child(format!("exited with {} before it was killed", status)));
+                }
+            },
+        }
+    }
+    child.start_kill().map_err(Error::io("killing the child"))?;
+
+    // Whole lines still in the pipe were printed before the kill
+    while let Some(line) = lines.next_line().await.map_err(Error::io("reading the child's output"))? {
+        acked.insert(sequence(&line)?);
+    }
+    child.wait().await.map_err(Error::io("waiting for the child"))?;
+
+    if power_loss {
+        lose_power(&file_path, durability, &acked, rng).map_err(Error::io("simulating power loss"))?;
+    }
+
+    let result = recover(&file_path, &acked).map_err(Error::io("recovering the log file"))?;
+    dir.close().map_err(Error::io("deleting the temp directory"))?;
+    Ok(result)
+}
+
+// A sequence number the child printed
+fn sequence(line: &str) -> Result<u64> {
+    line.trim().parse().map_err(|_| Error::Child(format!("printed {:?} instead of a sequence number", line)))
+}
+
+// Anything that was never synced only lived in the page cache, so a power loss can cut the file anywhere
+// after the last synced write. This models losing an unsynced tail, not holes or reordered writeback.
+fn lose_power(path: &Path, durability: Durability, acked: &HashSet<u64>, rng: &mut StdRng) -> io::Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    let durable = match durability {
+        Durability::Written => 0,
+        // Acked writes were synced before their ack
+        Durability::Synced => {
+            let mut durable = 0;
+            for frame in RecordReader::open(path)? {
+                if let Frame::Record { offset, record } = frame? {
+                    if acked.contains(&record.sequence) {
+                        durable = durable.max(offset + record.encoded_len() as u64);
+                    }
+                }
+            }
+            durable
+        }
+    };
+    let cut = rng.gen_range(durable..=len);
+    std::fs::OpenOptions::new().write(true).open(path)?.set_len(cut)
+}
+
+fn recover(path: &Path, acked: &HashSet<u64>) -> io::Result<CrashRun> {
+    let mut intact = HashSet::new();
+    let mut result = CrashRun { acked: acked.len() as u64, lost: 0, torn_tail: false, corrupted: 0 };
+    for frame in RecordReader::open(path)? {
+        match frame? {
+            Frame::Record { record, .. } => {
+                intact.insert(record.sequence);
+            }
+            Frame::Corrupted { .. } => result.corrupted += 1,
+            Frame::Torn { .. } => result.torn_tail = true,
+        }
+    }
+    result.lost = acked.iter().filter(|sequence| !intact.contains(sequence)).count() as u64;
+    Ok(result)
+}
+
+// crash-child: writes with a strategy until killed, printing each acked sequence number
+pub async fn child(tests: Vec<Box<dyn LoadTest>>, args: impl Iterator<Item = String>) -> Result<()> {
+    let mut name = None;
+    let mut file_path = None;
+    let mut clients = 8;
+    let mut config = RunConfig::default();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--test" => name = Some(value()?),
+            "--file" => file_path = Some(PathBuf::from(value()?)),
+            "--durability" => config.durability = Durability::parse(&value()?)?,
+            "--clients" => clients = parse(&value()?)?,
+            _ => return Err(Error::argument(format!("Unknown argument {}", arg))),
+        }
+    }
+    let name = name.ok_or_else(|| Error::argument("Missing --test"))?;
+    let file_path = file_path.ok_or_else(|| Error::argument("Missing --file"))?;
+    let test = tests
+        .into_iter()
+        .find(|test| test.get_name() == name)
+        .ok_or_else(|| Error::argument(format!("Unknown test {}", name)))?;
+
+    let storage = Storage::new(&config, &GrowthPolicy::None);
+    let writer = Arc::new(test.open(&file_path, &config, &storage, Capacity::Default).await?);
+    let messages = Arc::new(MessageSource::default());
+    let mut tasks: Vec<JoinHandle<Result<()>>> = Vec::new();
+    for client in 0..clients {
+        let writer = Arc::clone(&writer);
+        let messages = Arc::clone(&messages);
+        let size_range = config.size_range.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut rng = StdRng::from_entropy();
+            loop {
+                let record = messages.random(client as u32, &mut rng, &size_range);
+                let sequence = record.sequence;
+                let ack = writer.write(record).await?;
+                if ack.await.map_err(|_| Error::ChannelClosed("ack"))?.is_ok() {
+                    println!("{}", sequence);
+                }
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.map_err(Error::task)??;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{Record, HEADER_LEN};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // Records 0..count back to back, each taking 100 bytes
+    fn log(count: u64) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        for sequence in 0..count {
+            file.write_all(&Record::generated(sequence, 0, 100 - HEADER_LEN).encode()).unwrap();
+        }
+        file
+    }
+
+    fn damage(file: &NamedTempFile, offset: u64) {
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes[offset as usize] ^= 0xff;
+        std::fs::write(file.path(), bytes).unwrap();
+    }
+
+    #[test]
+    fn recovers_an_intact_file() {
+        let file = log(5);
+        let result = recover(file.path(), &(0..5).collect()).unwrap();
+        assert_eq!((result.acked, result.lost, result.corrupted, result.torn_tail), (5, 0, 0, false));
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn passes_with_damage_from_unacked_writes() {
+        let file = log(5);
+        damage(&file, 250);
+        std::fs::OpenOptions::new().write(true).open(file.path()).unwrap().set_len(470).unwrap();
+        let result = recover(file.path(), &[0, 1, 3].into_iter().collect()).unwrap();
+        assert_eq!((result.lost, result.corrupted, result.torn_tail), (0, 1, true));
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn fails_when_an_acked_write_is_damaged() {
+        let file = log(5);
+        damage(&file, 250);
+        let result = recover(file.path(), &(0..5).collect()).unwrap();
+        assert_eq!((result.lost, result.corrupted), (1, 1));
+        assert!(!result.passed());
+    }
+
+    #[test]
+    fn power_loss_keeps_synced_writes() {
+        let acked: HashSet<u64> = (0..3).collect();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let file = log(5);
+            lose_power(file.path(), Durability::Synced, &acked, &mut rng).unwrap();
+            let len = std::fs::metadata(file.path()).unwrap().len();
+            assert!((300..=500).contains(&len), "cut at {}", len);
+            assert!(recover(file.path(), &acked).unwrap().passed());
+        }
+    }
+
+    #[test]
+    fn power_loss_can_cut_unsynced_writes_anywhere() {
+        let acked: HashSet<u64> = (0..5).collect();
+        let mut rng = StdRng::seed_from_u64(1);
+        let lens: Vec<u64> = (0..20)
+            .map(|_| {
+                let file = log(5);
+                lose_power(file.path(), Durability::Written, &acked, &mut rng).unwrap();
+                std::fs::metadata(file.path()).unwrap().len()
+            })
+            .collect();
+        assert!(lens.iter().all(|len| *len <= 500));
+        assert!(lens.iter().any(|len| *len < 300), "never cut into the first 3 records: {:?}", lens);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant as StdInstant};
+
+use tokio::sync::{oneshot, Semaphore};
+use tokio::time::Instant;
+
+use crate::error::{Error, Result};
+use crate::run_config::parse;
+
+// Timing of a simulated storage device
+#[derive(Clone, Debug)]
+pub struct DeviceModel {
+    pub name: String,
+    // Bytes per second, shared by everything in flight
+    pub bandwidth: f64,
+    // Added to every write, overlaps with other writes up to the queue depth
+    pub op_latency: Duration,
+    // Cost of a flush to stable storage, once everything queued before it is done
+    pub fsync_latency: Duration,
+    // Operations the device works on at once, the rest wait for a slot
+    pub queue_depth: usize,
+}
+
+impl DeviceModel {
+    fn new(name: &str, bandwidth_mb: f64, op_latency_us: u64, fsync_latency_us: u64, queue_depth: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            bandwidth: bandwidth_mb * 1_000_000.0,
+            op_latency: Duration::from_micros(op_latency_us),
+            fsync_latency: Duration::from_micros(fsync_latency_us),
+            queue_depth,
+        }
+    }
+
+    // Rough figures for each class of device, not any particular model
+    pub fn preset(name: &str) -> Result<Self> {
+        match name {
+            "hdd" => Ok(Self::new(name, 150.0, 4_000, 12_000, 1)),
+            "sata-ssd" => Ok(Self::new(name, 500.0, 80, 1_000, 32)),
+            "nvme" => Ok(Self::new(name, 3_000.0, 20, 200, 256)),
+            "network" => Ok(Self::new(name, 250.0, 1_000, 2_000, 16)),
+            _ => Err(Error::argument(format!("Unknown device {}, expected hdd, sata-ssd, nvme or network", name))),
+        }
+    }
+
+    // A preset followed by any overrides, e.g. nvme,latency=50,queue=32. Bandwidth is in MB/s and
+    // latencies in microseconds.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut fields = spec.split(',');
+        let mut model = Self::preset(fields.next().unwrap_or_default())?;
+        for field in fields {
+            let (name, value) =
+                field.split_once('=').ok_or_else(|| Error::argument(format!("Invalid device setting {}", field)))?;
+            match name {
+                "bandwidth" => model.bandwidth = parse::<f64>(value)? * 1_000_000.0,
+                "latency" => model.op_latency = Duration::from_micros(parse(value)?),
+                "fsync" => model.fsync_latency = Duration::from_micros(parse(value)?),
+                "queue" => model.queue_depth = parse::<usize>(value)?.max(1),
+                _ => return Err(Error::argument(format!("Unknown device setting {}", name))),
+            }
+        }
+        Ok(model)
+    }
+}
+
+impl fmt::Display for DeviceModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:.0} MB/s, {} us per write, {} us fsync, queue depth {})",
+            self.name,
+            self.bandwidth / 1_000_000.0,
+            self.op_latency.as_micros(),
+            self.fsync_latency.as_micros(),
+            self.queue_depth
+        )
+    }
+}
+
+// A device shared by every file of a run, writes wait out the time the model says they'd take
+pub struct Device {
+    model: DeviceModel,
+    queue: Semaphore,
+    // When the device finishes transferring everything handed to it so far
+    busy_until: Mutex<Instant>,
+}
+
+impl Device {
+    pub fn new(model: &DeviceModel) -> Self {
+        Self { model: model.clone(), queue: Semaphore::new(model.queue_depth), busy_until: Mutex::new(Instant::now()) }
+    }
+
+    pub async fn write(&self, len: usize) {
+        let _slot = self.queue.acquire().await.expect("Device queue closed");
+        let transferred = {
+            let mut busy_until = self.busy_until.lock().expect("Device lock poisoned");
+            *busy_until = (*busy_until).max(Instant::now()) + Duration::from_secs_f64(len as f64 / self.model.bandwidth);
+            *busy_until
+        };
+        wait_until(transferred + self.model.op_latency).await;
+    }
+
+    pub async fn sync(&self) {
+        let _slot = self.queue.acquire().await.expect("Device queue closed");
+        // Nothing else transfers while the device flushes
+        let flushed = {
+            let mut busy_until = self.busy_until.lock().expect("Device lock poisoned");
+            *busy_until = (*busy_until).max(Instant::now()) + self.model.fsync_latency;
+            *busy_until
+        };
+        wait_until(flushed).await;
+    }
+}
+
+async fn wait_until(deadline: Instant) {
+    if deadline <= Instant::now() {
+        return;
+    }
+    let (wake, woken) = oneshot::channel();
+    Timer::get().add(deadline.into_std(), wake);
+    // The timer thread never drops a waiter without waking it
+    let _ = woken.await;
+}
+
+// Tokio's timer only has millisecond resolution, far coarser than the devices being modelled. This thread
+// sleeps until the next deadline with its timer slack turned down instead, which gets within microseconds of it
+// without spinning on the runtime and adding to the run's CPU figures.
+struct Timer {
+    waiters: Mutex<Waiters>,
+    changed: Condvar,
+}
+
+// Thread id of the timer once it's running
+static TIMER_THREAD: AtomicI32 = AtomicI32::new(0);
+
+// The timer thread, so its CPU time can be left out of a run's resource figures
+pub fn timer_thread() -> Option<libc::pid_t> {
+    Some(TIMER_THREAD.load(Ordering::Relaxed)).filter(|tid| *tid != 0)
+}
+
+#[derive(Default)]
+struct Waiters {
+    // By deadline, the id keeps equal deadlines apart
+    pending: BTreeMap<(StdInstant, u64), oneshot::Sender<()>>,
+    next_id: u64,
+}
+
+impl Timer {
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+        TIMER.get_or_init(|| {
+            let timer: &'static Timer = Box::leak(Box::new(Timer { waiters: Mutex::default(), changed: Condvar::new() }));
+            thread::Builder::new()
+                .name("device-timer".to_string())
+                .spawn(move || timer.run())
+                .expect("Failed to start the device timer");
+            timer
+        })
+    }
+
+    fn add(&self, deadline: StdInstant, wake: oneshot::Sender<()>) {
+        let mut waiters = self.waiters.lock().expect("Timer lock poisoned");
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.pending.insert((deadline, id), wake);
+        self.changed.notify_one();
+    }
+
+    fn run(&self) {
+        // SAFETY: only changes how late the kernel may wake this thread from its own timed waits
+        unsafe { libc::prctl(libc::PR_SET_TIMERSLACK, 1 as libc::c_ulong) };
+        // SAFETY: gettid has no preconditions
+        TIMER_THREAD.store(unsafe { libc::gettid() }, Ordering::Relaxed);
+        let mut waiters = self.waiters.lock().expect("Timer lock poisoned");
+        loop {
+            let now = StdInstant::now();
+            while let Some(entry) = waiters.pending.first_entry().filter(|entry| entry.key().0 <= now) {
+                let _ = entry.remove().send(());
+            }
+            waiters = match waiters.pending.keys().next() {
+                Some(&(deadline, _)) => self.changed.wait_timeout(waiters, deadline - now).expect("Timer lock poisoned").0,
+                None => self.changed.wait(waiters).expect("Timer lock poisoned"),
+            };
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+use std::io;
+
+use tokio::task::JoinError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Anything that stops a scenario from producing a result
+#[derive(Debug)]
+pub enum Error {
+    // Getting the run ready: the arguments and input files, the temp directory, the log file, the strategy's writers
+    Setup { context: &'static str, source: io::Error },
+    // IO outside the write path, write errors go through the strategy's error policy instead
+    Io { context: &'static str, source: io::Error },
+    // A channel to or from a writer closed early, usually because the task on the other end died
+    ChannelClosed(&'static str),
+    // A writer or producer task panicked or was cancelled
+    Task(String),
+    // The file couldn't be checked, or didn't hold what the strategy acked
+    Verification(String),
+    // A crash test's child process stopped before it was killed
+    Child(String),
+}
+
+impl Error {
+    pub fn setup(context: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| Error::Setup { context, source }
+    }
+
+    // A command line argument that doesn't make sense
+    pub fn argument(message: impl fmt::Display) -> Self {
+        Self::invalid("parsing the arguments", message)
+    }
+
+    // An input file that doesn't hold what it should
+    pub fn invalid(context: &'static str, message: impl fmt::Display) -> Self {
+        Error::Setup { context, source: io::Error::new(io::ErrorKind::InvalidInput, message.to_string()) }
+    }
+
+    pub fn io(context: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| Error::Io { context, source }
+    }
+
+    pub fn task(error: JoinError) -> Self {
+        Error::Task(error.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Setup { context, source } => write!(f, "setup failed {}: {}", context, source),
+            Error::Io { context, source } => write!(f, "IO error {}: {}", context, source),
+            Error::ChannelClosed(channel) => write!(f, "{} channel closed", channel),
+            Error::Task(error) => write!(f, "task failed: {}", error),
+            Error::Verification(error) => write!(f, "verification failed: {}", error),
+            Error::Child(error) => write!(f, "child process failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Setup { source, .. } | Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::{thread_rng, Rng};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::error::Error;
+use crate::load_test::WriteError;
+use crate::run_config::parse;
+use crate::storage::{LogFile, Position, PositionalFile};
+
+// Attempts a retrying strategy makes before giving up on a request, EINTR doesn't count
+const MAX_RETRIES: u32 = 5;
+const RETRY_BACKOFF: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    // The call writes part of the buffer
+    ShortWrite,
+    Interrupted,
+    NoSpace,
+    Io,
+}
+
+impl FaultKind {
+    fn parse(name: &str) -> crate::error::Result<Self> {
+        match name {
+            "short" => Ok(FaultKind::ShortWrite),
+            "eintr" => Ok(FaultKind::Interrupted),
+            "enospc" => Ok(FaultKind::NoSpace),
+            "eio" => Ok(FaultKind::Io),
+            _ => Err(Error::argument(format!("Unknown fault {}, expected short, eintr, enospc or eio", name))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FaultKind::ShortWrite => "short",
+            FaultKind::Interrupted => "eintr",
+            FaultKind::NoSpace => "enospc",
+            FaultKind::Io => "eio",
+        }
+    }
+
+    fn error(self) -> io::Error {
+        match self {
+            FaultKind::ShortWrite => unreachable!("Short writes aren't errors"),
+            FaultKind::Interrupted => io::Error::from_raw_os_error(libc::EINTR),
+            FaultKind::NoSpace => io::Error::from_raw_os_error(libc::ENOSPC),
+            FaultKind::Io => io::Error::from_raw_os_error(libc::EIO),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FaultConfig {
+    // Chance of each kind of fault on any one write call
+    pub rates: Vec<(FaultKind, f64)>,
+    // Faults injected once, on the write call that covers the file offset
+    pub offsets: Vec<(FaultKind, u64)>,
+    // Added to every write call
+    pub latency: Duration,
+}
+
+impl FaultConfig {
+    // Comma separated list of KIND=RATE, KIND@OFFSET and latency=MICROS, e.g. eio=0.001,enospc@1048576
+    pub fn parse(spec: &str) -> crate::error::Result<Self> {
+        let mut config = Self::default();
+        for fault in spec.split(',') {
+            if let Some((kind, offset)) = fault.split_once('@') {
+                config.offsets.push((FaultKind::parse(kind)?, parse(offset)?));
+                continue;
+            }
+            let (name, value) = fault.split_once('=').ok_or_else(|| Error::argument(format!("Invalid fault {}", fault)))?;
+            if name == "latency" {
+                config.latency = Duration::from_micros(parse(value)?);
+                continue;
+            }
+            let rate: f64 = parse(value)?;
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(Error::argument(format!("Fault rate {} must be between 0 and 1", rate)));
+            }
+            config.rates.push((FaultKind::parse(name)?, rate));
+        }
+        Ok(config)
+    }
+}
+
+// Back in the form `parse` takes
+impl fmt::Display for FaultConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut faults: Vec<String> = self.rates.iter().map(|(kind, rate)| format!("{}={}", kind.name(), rate)).collect();
+        faults.extend(self.offsets.iter().map(|(kind, offset)| format!("{}@{}", kind.name(), offset)));
+        if !self.latency.is_zero() {
+            faults.push(format!("latency={}", self.latency.as_micros()));
+        }
+        match faults.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", faults.join(",")),
+        }
+    }
+}
+
+// What a write call should do instead of, or as well as, writing
+pub enum Injected {
+    Pass,
+    // Write only this many bytes
+    Short(usize),
+    Error(io::Error),
+}
+
+// Decides which write calls fail, shared by every file a run opens
+pub struct FaultInjector {
+    config: FaultConfig,
+    // Offset faults that haven't fired yet
+    pending: Mutex<Vec<(FaultKind, u64)>>,
+    stats: Arc<FaultStats>,
+}
+
+impl FaultInjector {
+    pub fn new(config: &FaultConfig, stats: Arc<FaultStats>) -> Self {
+        Self { config: config.clone(), pending: Mutex::new(config.offsets.clone()), stats }
+    }
+
+    pub fn latency(&self) -> Duration {
+        self.config.latency
+    }
+
+    // Called at the start of every write call of `len` bytes at `offset` in the file
+    pub fn next(&self, offset: u64, len: usize) -> Injected {
+        if self.config.rates.is_empty() && self.config.offsets.is_empty() {
+            return Injected::Pass;
+        }
+        let at_offset = {
+            let mut pending = self.pending.lock().expect("Fault lock poisoned");
+            let covered = pending.iter().position(|(_, at)| (offset..offset + len as u64).contains(at));
+            covered.map(|index| pending.remove(index).0)
+        };
+        let kind = at_offset.or_else(|| {
+            let mut rng = thread_rng();
+            self.config.rates.iter().find(|(_, rate)| rng.gen_bool(*rate)).map(|(kind, _)| *kind)
+        });
+
+        let stats = &self.stats;
+        match kind {
+            None => Injected::Pass,
+            // Can't write less than one byte without it looking like the end of the file
+            Some(FaultKind::ShortWrite) if len < 2 => Injected::Pass,
+            Some(FaultKind::ShortWrite) => {
+                stats.short_writes.fetch_add(1, Ordering::Relaxed);
+                Injected::Short(thread_rng().gen_range(1..len))
+            }
+            Some(kind) => {
+                let counter = match kind {
+                    FaultKind::Interrupted => &stats.interrupted,
+                    FaultKind::NoSpace => &stats.no_space,
+                    _ => &stats.io_errors,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                Injected::Error(kind.error())
+            }
+        }
+    }
+}
+
+// Faults injected during a run and how the strategy handled every error it saw, injected or not
+#[derive(Default)]
+pub struct FaultStats {
+    short_writes: AtomicU64,
+    interrupted: AtomicU64,
+    no_space: AtomicU64,
+    io_errors: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+    aborted: AtomicU64,
+    // Bytes written by requests that then failed, which can hold part of a record or nothing at all
+    abandoned: Mutex<Vec<Range<u64>>>,
+}
+
+impl FaultStats {
+    fn abandon(&self, range: Range<u64>) {
+        if !range.is_empty() {
+            self.abandoned.lock().expect("Abandoned lock poisoned").push(range);
+        }
+    }
+
+    pub fn abandoned(&self) -> Vec<Range<u64>> {
+        self.abandoned.lock().expect("Abandoned lock poisoned").clone()
+    }
+
+    pub fn report(&self) -> FaultReport {
+        let get = |counter: &AtomicU64| counter.load(Ordering::SeqCst);
+        FaultReport {
+            short_writes: get(&self.short_writes),
+            interrupted: get(&self.interrupted),
+            no_space: get(&self.no_space),
+            io_errors: get(&self.io_errors),
+            retried: get(&self.retried),
+            failed: get(&self.failed),
+            aborted: get(&self.aborted),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FaultReport {
+    pub short_writes: u64,
+    pub interrupted: u64,
+    pub no_space: u64,
+    pub io_errors: u64,
+    // Errors handled by trying the call again
+    pub retried: u64,
+    // Errors handed back to the producer as a failed write
+    pub failed: u64,
+    // Errors that ended the run
+    pub aborted: u64,
+}
+
+impl FaultReport {
+    pub fn is_empty(&self) -> bool {
+        self.short_writes + self.interrupted + self.no_space + self.io_errors + self.retried + self.failed + self.aborted == 0
+    }
+}
+
+// What a strategy does when a write call fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    // Try again a few times before failing the request
+    Retry,
+    // Fail the request and carry on with the next one
+    FailRequest,
+    // Fail the request and every one after it
+    AbortRun,
+}
+
+impl fmt::Display for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorPolicy::Retry => "retry",
+            ErrorPolicy::FailRequest => "fail request",
+            ErrorPolicy::AbortRun => "abort run",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Writes through a strategy's error policy. Short writes are always resumed and EINTR always retried,
+// whatever the policy, the same as a careful caller of write(2) would.
+#[derive(Clone)]
+pub struct ErrorHandler {
+    policy: ErrorPolicy,
+    stats: Arc<FaultStats>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl ErrorHandler {
+    pub fn new(policy: ErrorPolicy, stats: Arc<FaultStats>) -> Self {
+        Self { policy, stats, aborted: Arc::new(AtomicBool::new(false)) }
+    }
+
+    // Refuses every request once the run has been aborted
+    fn check(&self) -> Result<(), WriteError> {
+        match self.aborted.load(Ordering::SeqCst) {
+            true => Err(WriteError::Aborted),
+            false => Ok(()),
+        }
+    }
+
+    // What to do about a failed call, Ok holds how long to wait before trying it again
+    fn decide(&self, error: io::Error, attempt: &mut u32) -> Result<Duration, WriteError> {
+        if error.kind() == ErrorKind::Interrupted {
+            self.stats.retried.fetch_add(1, Ordering::Relaxed);
+            return Ok(Duration::ZERO);
+        }
+        match self.policy {
+            ErrorPolicy::Retry if *attempt < MAX_RETRIES => {
+                *attempt += 1;
+                self.stats.retried.fetch_add(1, Ordering::Relaxed);
+                Ok(RETRY_BACKOFF * *attempt)
+            }
+            ErrorPolicy::Retry | ErrorPolicy::FailRequest => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                Err(WriteError::Failed)
+            }
+            ErrorPolicy::AbortRun => {
+                self.stats.aborted.fetch_add(1, Ordering::Relaxed);
+                self.aborted.store(true, Ordering::SeqCst);
+                Err(WriteError::Aborted)
+            }
+        }
+    }
+
+    // Writes a whole request and flushes it to the file. If it fails, whatever it left in the file is recorded
+    // so verification can tell it apart from corruption.
+    pub async fn append<W: AsyncWrite + Position + Unpin>(&self, writer: &mut W, buf: &[u8]) -> Result<(), WriteError> {
+        self.append_batch(writer, &[buf]).await
+    }
+
+    // Writes several requests and flushes them to the file together, the batch succeeds or fails as a whole
+    pub async fn append_batch<W: AsyncWrite + Position + Unpin>(
+        &self,
+        writer: &mut W,
+        bufs: &[&[u8]],
+    ) -> Result<(), WriteError> {
+        let start = writer.position();
+        let mut result = Ok(());
+        for buf in bufs {
+            result = self.write_all(writer, buf).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = self.flush(writer).await;
+        }
+        if result.is_err() {
+            self.stats.abandon(start..writer.position());
+        }
+        result
+    }
+
+    async fn write_all<W: AsyncWrite + Unpin>(&self, writer: &mut W, buf: &[u8]) -> Result<(), WriteError> {
+        self.check()?;
+        let (mut written, mut attempt) = (0, 0);
+        while written < buf.len() {
+            match writer.write(&buf[written..]).await {
+                Ok(0) => tokio::time::sleep(self.decide(ErrorKind::WriteZero.into(), &mut attempt)?).await,
+                Ok(len) => written += len,
+                Err(error) => tokio::time::sleep(self.decide(error, &mut attempt)?).await,
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn flush<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<(), WriteError> {
+        self.check()?;
+        let mut attempt = 0;
+        while let Err(error) = writer.flush().await {
+            tokio::time::sleep(self.decide(error, &mut attempt)?).await;
+        }
+        Ok(())
+    }
+
+    pub async fn sync_data(&self, file: &LogFile) -> Result<(), WriteError> {
+        self.check()?;
+        let mut attempt = 0;
+        while let Err(error) = file.sync_data().await {
+            tokio::time::sleep(self.decide(error, &mut attempt)?).await;
+        }
+        Ok(())
+    }
+
+    // Blocking versions for strategies that write from blocking threads
+    // The whole range was reserved for the request, so a failure abandons all of it, written or not
+    pub fn write_all_at(&self, file: &PositionalFile, buf: &[u8], offset: u64) -> Result<(), WriteError> {
+        self.check()?;
+        let (mut written, mut attempt) = (0, 0);
+        while written < buf.len() {
+            let waited = match file.write_at(&buf[written..], offset + written as u64) {
+                Ok(0) => self.decide(ErrorKind::WriteZero.into(), &mut attempt),
+                Ok(len) => {
+                    written += len;
+                    continue;
+                }
+                Err(error) => self.decide(error, &mut attempt),
+            };
+            match waited {
+                Ok(backoff) => std::thread::sleep(backoff),
+                Err(error) => {
+                    self.stats.abandon(offset..offset + buf.len() as u64);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sync_data_blocking(&self, file: &PositionalFile) -> Result<(), WriteError> {
+        self.check()?;
+        let mut attempt = 0;
+        while let Err(error) = file.sync_data() {
+            std::thread::sleep(self.decide(error, &mut attempt)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(spec: &str) -> String {
+        FaultConfig::parse(spec).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_fault_specs() {
+        let config = FaultConfig::parse("eio=0.001,short=1,enospc@1048576,latency=250").unwrap();
+        assert_eq!(config.rates, vec![(FaultKind::Io, 0.001), (FaultKind::ShortWrite, 1.0)]);
+        assert_eq!(config.offsets, vec![(FaultKind::NoSpace, 1_048_576)]);
+        assert_eq!(config.latency, Duration::from_micros(250));
+    }
+
+    #[test]
+    fn displays_in_the_form_it_parses() {
+        let spec = "eintr=0.5,eio@4096,latency=10";
+        assert_eq!(FaultConfig::parse(spec).unwrap().to_string(), spec);
+        assert_eq!(FaultConfig::default().to_string(), "none");
+    }
+
+    #[test]
+    fn rejects_a_rate_above_1() {
+        assert!(error("eio=1.5").contains("must be between 0 and 1"));
+    }
+
+    #[test]
+    fn rejects_an_unknown_kind() {
+        assert!(error("ebadf=0.1").contains("Unknown fault"));
+    }
+
+    #[test]
+    fn rejects_a_fault_without_a_rate() {
+        assert!(error("eio").contains("Invalid fault"));
+    }
+}
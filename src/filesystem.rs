@@ -0,0 +1,179 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+// The filesystem a directory is on, from /proc/self/mountinfo and statfs
+#[derive(Clone, Debug)]
+pub struct FilesystemInfo {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String,
+    // Options for this mount, e.g. noatime
+    pub mount_options: String,
+    // Options for the filesystem itself, e.g. data=ordered
+    pub super_options: String,
+    pub block_size: u64,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+}
+
+impl FilesystemInfo {
+    pub fn detect(dir: &Path) -> io::Result<Self> {
+        let path = fs::canonicalize(dir)?;
+
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+        let mut info = covering(&mountinfo, &path)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no mount covers the directory"))?;
+
+        let stat = statfs(&path)?;
+        info.block_size = stat.f_bsize as u64;
+        info.total_bytes = stat.f_blocks * stat.f_bsize as u64;
+        info.free_bytes = stat.f_bavail * stat.f_bsize as u64;
+        Ok(info)
+    }
+
+    // Writes to these never reach a disk
+    pub fn is_memory_backed(&self) -> bool {
+        matches!(self.fs_type.as_str(), "tmpfs" | "ramfs")
+    }
+}
+
+impl fmt::Display for FilesystemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} at {} ({}; {}), {} byte blocks, {:.2} GB free of {:.2} GB",
+            self.fs_type,
+            self.source,
+            self.mount_point.display(),
+            self.mount_options,
+            self.super_options,
+            self.block_size,
+            self.free_bytes as f64 / 1_000_000_000.0,
+            self.total_bytes as f64 / 1_000_000_000.0
+        )
+    }
+}
+
+// The most specific mount covering the path, a later mount on the same point hides the earlier one
+fn covering(mountinfo: &str, path: &Path) -> Option<FilesystemInfo> {
+    let mut covering: Option<FilesystemInfo> = None;
+    for mount in mountinfo.lines().filter_map(parse_mount) {
+        let deeper = covering.as_ref().is_none_or(|c| mount.mount_point.as_os_str().len() >= c.mount_point.as_os_str().len());
+        if path.starts_with(&mount.mount_point) && deeper {
+            covering = Some(mount);
+        }
+    }
+    covering
+}
+
+// A mountinfo line looks like
+//   36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+// with any number of optional fields before the `-`
+fn parse_mount(line: &str) -> Option<FilesystemInfo> {
+    let (mount, filesystem) = line.split_once(" - ")?;
+    let mount: Vec<&str> = mount.split(' ').collect();
+    let mut filesystem = filesystem.split(' ');
+    Some(FilesystemInfo {
+        mount_point: PathBuf::from(unescape(mount.get(4)?)),
+        mount_options: mount.get(5)?.to_string(),
+        fs_type: filesystem.next()?.to_string(),
+        source: unescape(filesystem.next()?),
+        super_options: filesystem.next().unwrap_or_default().to_string(),
+        block_size: 0,
+        total_bytes: 0,
+        free_bytes: 0,
+    })
+}
+
+// Spaces, tabs, newlines and backslashes in paths are written as octal escapes like \040
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match octal {
+            Some(byte) if bytes[i] == b'\\' => {
+                out.push(byte);
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn statfs(path: &Path) -> io::Result<libc::statfs> {
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: the path is NUL terminated and `stat` is only read after statfs has filled it in
+    if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { stat.assume_init() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = r"22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw,errors=remount-ro
+30 22 0:26 / /tmp rw,nosuid,nodev shared:12 - tmpfs tmpfs rw,size=8g
+41 22 259:3 / /mnt/data rw,noatime shared:20 master:3 - xfs /dev/nvme1n1 rw,attr2,inode64
+42 41 259:4 / /mnt/data/fast rw,noatime - ext4 /dev/nvme2n1 rw
+43 22 0:40 / /mnt/my\040disk rw - ext4 /dev/sd\134b rw
+44 30 0:27 / /tmp rw - ramfs ramfs rw
+";
+
+    #[test]
+    fn parses_a_mount_line() {
+        let mount = parse_mount(MOUNTINFO.lines().next().unwrap()).unwrap();
+        assert_eq!(mount.mount_point, Path::new("/"));
+        assert_eq!(mount.mount_options, "rw,relatime");
+        assert_eq!((mount.fs_type.as_str(), mount.source.as_str()), ("ext4", "/dev/nvme0n1p2"));
+        assert_eq!(mount.super_options, "rw,errors=remount-ro");
+    }
+
+    #[test]
+    fn skips_optional_fields_before_the_separator() {
+        let mount = parse_mount(MOUNTINFO.lines().nth(2).unwrap()).unwrap();
+        assert_eq!(mount.mount_point, Path::new("/mnt/data"));
+        assert_eq!(mount.mount_options, "rw,noatime");
+        assert_eq!((mount.fs_type.as_str(), mount.super_options.as_str()), ("xfs", "rw,attr2,inode64"));
+
+        let mount = parse_mount(MOUNTINFO.lines().nth(3).unwrap()).unwrap();
+        assert_eq!(mount.fs_type, "ext4");
+        assert!(parse_mount("22 1 259:2 / / rw shared:1").is_none());
+    }
+
+    #[test]
+    fn unescapes_octal_escapes() {
+        assert_eq!(unescape(r"/mnt/my\040disk"), "/mnt/my disk");
+        assert_eq!(unescape(r"a\011b\012c\134d"), "a\tb\nc\\d");
+        // Not an escape unless three octal digits follow
+        assert_eq!(unescape(r"a\09b\04"), r"a\09b\04");
+        assert_eq!(parse_mount(MOUNTINFO.lines().nth(4).unwrap()).unwrap().source, r"/dev/sd\b");
+    }
+
+    #[test]
+    fn picks_the_deepest_mount() {
+        let fs_type = |path: &str| covering(MOUNTINFO, Path::new(path)).map(|mount| mount.fs_type);
+        assert_eq!(fs_type("/home/user").as_deref(), Some("ext4"));
+        assert_eq!(fs_type("/mnt/data/logs").as_deref(), Some("xfs"));
+        assert_eq!(fs_type("/mnt/data/fast/logs").as_deref(), Some("ext4"));
+        // A path only covers whole components, /mnt/database isn't under /mnt/data
+        assert_eq!(covering(MOUNTINFO, Path::new("/mnt/database")).unwrap().mount_point, Path::new("/"));
+        assert_eq!(covering(MOUNTINFO, Path::new("/mnt/my disk/x")).unwrap().mount_point, Path::new("/mnt/my disk"));
+        // A later mount on the same point hides the earlier one
+        assert_eq!(fs_type("/tmp/bench").as_deref(), Some("ramfs"));
+        assert!(covering(MOUNTINFO, Path::new("relative")).is_none());
+    }
+}
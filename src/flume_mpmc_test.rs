@@ -1,115 +1,120 @@
 use async_trait::async_trait;
-use tokio::fs::{File, self};
-use std::os::unix::prelude::FileExt;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use std::path::Path;
 use std::sync::Arc;
-use flume;
-use rand::{Rng, SeedableRng};
-use tempfile::tempdir;
 use std::any::type_name;
-use std::ops::Range;
-use std::time::Instant;
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::load_test::LoadTest;
-use crate::test_result::TestResult;
+use crate::ordering::OrderGuarantee;
+use crate::error::{Error, Result};
+use crate::fault::ErrorPolicy;
+use crate::load_test::{Ack, AckSender, LoadTest, LogWriter};
+use crate::queue::{Capacity, QueueGauge};
+use crate::record::Record;
+use crate::run_config::{Durability, RunConfig};
+use crate::storage::Storage;
 
 pub struct FlumeMpmcTest;
 
+struct FlumeMpmcWriter {
+    sender: flume::Sender<(Vec<u8>, AckSender)>,
+    writer_handles: Vec<JoinHandle<Result<()>>>,
+    queue: Arc<QueueGauge>,
+}
+
 #[async_trait]
 impl LoadTest for FlumeMpmcTest {
     fn get_name(&self) -> &str {
         type_name::<Self>()
     }
 
-    async fn run(&self, num_writes: usize, size_range: Range<usize>) -> TestResult {
-        // Create a temporary directory for the test
-        let dir = tempdir().expect("Failed to create temp directory");
-        let file_path = dir.path().join("test_log");
+    // Ten writers race each other to the file
+    fn ordering(&self) -> OrderGuarantee {
+        OrderGuarantee::None
+    }
+
+    // The failed write's slot in the file has already been handed out, so it's left as a hole
+    fn error_policy(&self) -> ErrorPolicy {
+        ErrorPolicy::FailRequest
+    }
+
+    fn channel(&self) -> bool {
+        true
+    }
 
+    async fn open(
+        &self,
+        file_path: &Path,
+        config: &RunConfig,
+        storage: &Storage,
+        capacity: Capacity,
+    ) -> Result<Box<dyn LogWriter>> {
         // Create a channel for sending messages to the file writer tasks
-        let (sender, receiver) = flume::unbounded::<Vec<u8>>();
+        let (sender, receiver) = capacity.flume::<(Vec<u8>, AckSender)>(None);
+        let queue = Arc::new(QueueGauge::new(capacity.bound(None)));
 
+        // Every write opens the file again, this makes sure it exists even if nothing gets written
+        storage.open_positional(file_path).map_err(Error::setup("creating the log file"))?;
         let offset = Arc::new(AtomicU64::new(0));
+        let errors = storage.error_handler(self.error_policy());
 
         // Spawn the file writer tasks
         let mut writer_handles = Vec::new();
         for _ in 0..10 {
             let receiver = receiver.clone();
-            let file_path = file_path.clone();
-            // let file_clone = file.try_clone().expect("Failed to clone file");
+            let file_path = file_path.to_path_buf();
             let offset = offset.clone();
+            let durability = config.durability;
+            let storage = storage.clone();
+            let errors = errors.clone();
+            let queue = Arc::clone(&queue);
             writer_handles.push(tokio::spawn(async move {
-                let mut results = Vec::new();
-                while let Ok(message) = receiver.recv_async().await {
-                    let duration_start = Instant::now();
+                while let Ok((message, ack)) = receiver.recv_async().await {
+                    queue.received(message.len());
                     let start_pos = offset.fetch_add(message.len() as u64, Ordering::SeqCst);
                     let file_path = file_path.clone();
+                    let storage = storage.clone();
+                    let errors = errors.clone();
                     let result = tokio::task::spawn_blocking(move || {
-                        let file = std::fs::OpenOptions::new()
-                            .create(true)
-                            .write(true)
-                            .truncate(false)
-                            .open(&file_path)
-                            .expect("Failed to create file");
-                        file.write_at(&message, start_pos)
-                    }).await.expect("Failed to write to file");
-                    match result {
-                        Ok(_) => (),
-                        Err(e) => panic!("Failed to write to file: {}", e),
-                    }
-                    let duration = Instant::now().duration_since(duration_start);
-
-                    // Add the result to the results vector
-                    results.push(duration.as_nanos() as f64);
+                        let file = storage.open_positional(&file_path)?;
+                        let mut result = errors.write_all_at(&file, &message, start_pos);
+                        if result.is_ok() && durability == Durability::Synced {
+                            result = errors.sync_data_blocking(&file);
+                        }
+                        Ok(result)
+                    }).await.map_err(Error::task)?.map_err(Error::io("opening the log file"))?;
+                    let _ = ack.send(result);
                 }
-                results
+                Ok(())
             }));
         }
 
-        // Spawn the producer tasks
-        for _ in 0..num_writes {
-            let mut rng = rand::rngs::StdRng::from_entropy();
-            let msg_size = rng.gen_range(size_range.clone());
-            let message = vec![0u8; msg_size];
-            let sender_clone = sender.clone();
+        Ok(Box::new(FlumeMpmcWriter { sender, writer_handles, queue }))
+    }
+}
+
+#[async_trait]
+impl LogWriter for FlumeMpmcWriter {
+    async fn write(&self, record: Record) -> Result<Ack> {
+        let message = record.encode();
+        let (ack, receiver) = oneshot::channel();
+        self.queue.send(message.len(), self.sender.send_async((message, ack))).await.map_err(|_| Error::ChannelClosed("writer"))?;
+        Ok(receiver)
+    }
 
-            tokio::spawn(async move {
-                sender_clone.send_async(message).await.expect("Failed to send message");
-            });
-        }
+    fn queue(&self) -> Option<Arc<QueueGauge>> {
+        Some(Arc::clone(&self.queue))
+    }
 
+    async fn close(self: Box<Self>) -> Result<()> {
         // Signal no more messages will be sent
-        drop(sender);
+        drop(self.sender);
 
         // Wait for all writer tasks to finish
-        let mut all_results = Vec::new();
-        for handle in writer_handles {
-            let mut results = handle.await.expect("Writer task panicked");
-            all_results.append(&mut results);
-        }
-
-        let metadata = fs::metadata(file_path).await.expect("Failed to get file metadata");
-        let file_size = metadata.len();
-        let bytes_written = offset.load(Ordering::SeqCst);
-        assert_eq!(file_size, bytes_written);
-        println!("File size {} matches {} bytes written", file_size, bytes_written);
-
-        dir.close().expect("Failed to delete temp directory");
-
-        // Calculate the mean, median, and p90
-        all_results.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let sum: f64 = all_results.iter().sum();
-        let mean = sum / num_writes as f64;
-        let median = all_results[num_writes / 2];
-        let p90 = all_results[(9 * num_writes) / 10];
-
-        // Cast the result of the `run` method to a `TestResult` object
-        TestResult {
-            mean,
-            median,
-            p90,
-            num_writes: num_writes as u64,
-            total_bytes: bytes_written,
+        for handle in self.writer_handles {
+            handle.await.map_err(Error::task)??;
         }
+        Ok(())
     }
 }
@@ -1,86 +1,110 @@
 use async_trait::async_trait;
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::sync::mpsc;
-use tokio::fs::File;
-use rand::{Rng, SeedableRng};
-use tempfile::tempdir;
+use tokio::io::BufWriter;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use std::any::type_name;
-use std::ops::Range;
-use std::time::Instant;
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::load_test::LoadTest;
-use crate::test_result::TestResult;
+use crate::ordering::OrderGuarantee;
+use crate::error::{Error, Result};
+use crate::fault::ErrorPolicy;
+use crate::load_test::{Ack, AckSender, LoadTest, LogWriter};
+use crate::queue::{Capacity, QueueGauge};
+use crate::record::Record;
+use crate::run_config::{Durability, RunConfig};
+use crate::storage::Storage;
+
+// Most messages written between two flushes
+const MAX_BATCH: usize = 256;
 
 pub struct FlumeMpscBufferedWriterTest;
 
+struct FlumeMpscBufferedWriter {
+    sender: flume::Sender<(Vec<u8>, AckSender)>,
+    writer_handle: JoinHandle<()>,
+    queue: Arc<QueueGauge>,
+}
+
 #[async_trait]
 impl LoadTest for FlumeMpscBufferedWriterTest {
     fn get_name(&self) -> &str {
         type_name::<Self>()
     }
 
-    async fn run(&self, num_writes: usize, size_range: Range<usize>) -> TestResult {
-        let mut results = Vec::new();
-        let mut total_bytes: u64 = 0;
+    fn ordering(&self) -> OrderGuarantee {
+        OrderGuarantee::Total
+    }
+
+    // After a failed flush there's no telling how much of the buffer reached the file
+    fn error_policy(&self) -> ErrorPolicy {
+        ErrorPolicy::AbortRun
+    }
 
-        // Create a temporary directory for the test
-        let dir = tempdir().expect("Failed to create temp directory");
-        let file_path = dir.path().join("test_log");
+    fn channel(&self) -> bool {
+        true
+    }
 
+    async fn open(
+        &self,
+        file_path: &Path,
+        config: &RunConfig,
+        storage: &Storage,
+        capacity: Capacity,
+    ) -> Result<Box<dyn LogWriter>> {
         // Create a channel for sending messages to the file writer task
-        let (sender, mut receiver) = flume::unbounded::<(Vec<u8>, Instant)>();
+        let (sender, receiver) = capacity.flume::<(Vec<u8>, AckSender)>(None);
+        let queue = Arc::new(QueueGauge::new(capacity.bound(None)));
 
         // Spawn the file writer task
+        let file = storage.create(file_path).await.map_err(Error::setup("creating the log file"))?;
+        let errors = storage.error_handler(self.error_policy());
+        let durability = config.durability;
+        let received = Arc::clone(&queue);
         let writer_handle = tokio::spawn(async move {
-            let file = File::create(&file_path).await.expect("Failed to create file");
             let mut buf_writer = BufWriter::new(file);
 
-            while let Ok((message, duration_start)) = receiver.recv_async().await {
-                buf_writer.write_all(&message).await.expect("Failed to write to file");
-                buf_writer.flush().await;
-                let duration = Instant::now().duration_since(duration_start);
-
-                // Add the result to the results vector
-                results.push(duration.as_nanos() as f64);
+            // Everything already queued behind a message goes into the buffer with it, and the whole batch is
+            // flushed, synced and acked together
+            while let Ok(first) = receiver.recv_async().await {
+                let mut batch = vec![first];
+                batch.extend(receiver.try_iter().take(MAX_BATCH - 1));
+                for (message, _) in &batch {
+                    received.received(message.len());
+                }
+                let messages: Vec<&[u8]> = batch.iter().map(|(message, _)| message.as_slice()).collect();
+                let mut result = errors.append_batch(&mut buf_writer, &messages).await;
+                if result.is_ok() && durability == Durability::Synced {
+                    result = errors.sync_data(buf_writer.get_ref()).await;
+                }
+                for (_, ack) in batch {
+                    let _ = ack.send(result);
+                }
             }
-            results
         });
 
-        // Spawn the producer tasks
-        for _ in 0..num_writes {
-            let mut rng = rand::rngs::StdRng::from_entropy();
-            let msg_size = rng.gen_range(size_range.clone());
-            total_bytes += msg_size as u64;
-            let message = vec![0u8; msg_size];
-            let sender_clone = sender.clone();
-
-            tokio::spawn(async move {
-                let start = Instant::now();
-                sender_clone.send_async((message, start)).await.expect("Failed to send message");
-            });
-        }
+        Ok(Box::new(FlumeMpscBufferedWriter { sender, writer_handle, queue }))
+    }
+}
+
+#[async_trait]
+impl LogWriter for FlumeMpscBufferedWriter {
+    async fn write(&self, record: Record) -> Result<Ack> {
+        let message = record.encode();
+        let (ack, receiver) = oneshot::channel();
+        self.queue.send(message.len(), self.sender.send_async((message, ack))).await.map_err(|_| Error::ChannelClosed("writer"))?;
+        Ok(receiver)
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
         // Signal no more messages will be sent
-        drop(sender);
+        drop(self.sender);
 
         // Wait for the writer task to finish
-        let mut results = writer_handle.await.expect("Writer task panicked");
-
-        dir.close().expect("Failed to delete temp directory");
-
-        // Calculate the mean, median, and p90
-        results.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let sum: f64 = results.iter().sum();
-        let mean = sum / num_writes as f64;
-        let median = results[num_writes / 2];
-        let p90 = results[(9 * num_writes) / 10];
-
-        // Cast the result of the `run` method to a `TestResult` object
-        TestResult {
-            mean,
-            median,
-            p90,
-            num_writes: num_writes as u64,
-            total_bytes,
-        }
+        self.writer_handle.await.map_err(Error::task)
+    }
+
+    fn queue(&self) -> Option<Arc<QueueGauge>> {
+        Some(Arc::clone(&self.queue))
     }
 }
@@ -0,0 +1,111 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::error::{Error, Result};
+use crate::output::{self, HostInfo};
+use crate::test_runner::TestRunner;
+
+// The commit the binary is being run from, and whether the work tree has changes on top of it
+fn git_commit() -> (Option<String>, bool) {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok().filter(|output| output.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let commit = git(&["rev-parse", "--short=12", "HEAD"]);
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    (commit, dirty)
+}
+
+// Appends every result of a run to a JSON Lines file, one line per scenario and repetition, each tagged with
+// the commit and host it came from
+pub fn append(path: &Path, runner: &TestRunner, host: &HostInfo) -> io::Result<()> {
+    let (commit, dirty) = git_commit();
+    let recorded_at = output::timestamp(SystemTime::now());
+    let config = output::config_json(runner);
+    let mut lines = String::new();
+    for run in &runner.test_runs {
+        let line = json!({
+            "commit": commit,
+            "dirty": dirty,
+            "hostname": host.hostname,
+            "recorded_at": recorded_at,
+            "seed": runner.config.seed,
+            "host": host.to_json(),
+            "config": config,
+            "result": output::run_json(run),
+        });
+        lines.push_str(&line.to_string());
+        lines.push('\n');
+    }
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(lines.as_bytes())
+}
+
+// history: prints how each scenario's numbers changed across the runs in a history file, oldest first
+pub fn show(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path = None;
+    let mut hostname = None;
+    let mut test = None;
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--host" => hostname = Some(value()?),
+            // Only tests whose name contains this
+            "--test" => test = Some(value()?),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(Error::argument(format!("Unknown argument {}", arg))),
+        }
+    }
+    let path = path.ok_or_else(|| Error::argument("Usage: history PATH [--host NAME] [--test NAME]"))?;
+    let history = fs::read_to_string(&path).map_err(Error::setup("reading the history"))?;
+
+    // Lines for the same scenario are grouped, in the order each scenario first appears
+    let mut scenarios: Vec<(String, Vec<Value>)> = Vec::new();
+    for line in history.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: Value = serde_json::from_str(line)
+            .map_err(|e| Error::invalid("reading the history", format!("{} has an invalid line: {}", path, e)))?;
+        let result = &entry["result"];
+        let name = result["test"].as_str().unwrap_or_default();
+        if hostname.as_ref().is_some_and(|hostname| entry["hostname"] != hostname.as_str())
+            || test.as_ref().is_some_and(|test| !name.contains(test.as_str()))
+        {
+            continue;
+        }
+        let scenario = output::scenario_name(result);
+        match scenarios.iter_mut().find(|(existing, _)| *existing == scenario) {
+            Some((_, entries)) => entries.push(entry),
+            None => scenarios.push((scenario, vec![entry])),
+        }
+    }
+
+    for (scenario, entries) in scenarios {
+        println!("===== {} =====", scenario);
+        println!(
+            "  {:<24} {:<14} {:<16} {:>12} {:>10} {:>10} {:>10}",
+            "Recorded", "Commit", "Host", "Writes/s", "P50 ms", "P99 ms", "Max ms"
+        );
+        for entry in entries {
+            let latency = &entry["result"]["latency"];
+            let ms = |field: &str| latency[field].as_f64().unwrap_or(0.0) / 1_000_000.0;
+            let commit = entry["commit"].as_str().unwrap_or("unknown");
+            println!(
+                "  {:<24} {:<14} {:<16} {:>12.2} {:>10.2} {:>10.2} {:>10.2}",
+                entry["recorded_at"].as_str().unwrap_or_default(),
+                if entry["dirty"].as_bool() == Some(true) { format!("{}+", commit) } else { commit.to_string() },
+                entry["hostname"].as_str().unwrap_or_default(),
+                entry["result"]["writes_per_s"].as_f64().unwrap_or(0.0),
+                ms("median_ns"),
+                ms("p99_ns"),
+                ms("max_ns"),
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
@@ -0,0 +1,395 @@
+use std::fmt::Write as _;
+
+use crate::compare::median;
+use crate::output::{self, HostInfo};
+use crate::queue::Capacity;
+use crate::test_runner::{TestRunResult, TestRunner};
+
+// Every chart is this size, with room on the right for the legend
+const WIDTH: f64 = 820.0;
+const HEIGHT: f64 = 340.0;
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 240.0;
+const TOP: f64 = 30.0;
+const BOTTOM: f64 = 45.0;
+
+// A strategy keeps its colour across every chart
+const COLORS: &[&str] = &["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f"];
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { padding: 2px 12px 2px 0; text-align: left; vertical-align: top; }
+svg { display: block; margin: 1em 0; }
+svg text { font-size: 12px; fill: #222; }
+svg .title { font-size: 14px; font-weight: bold; }
+svg .grid { stroke: #ddd; }
+svg .axis { stroke: #222; }";
+
+// A standalone page of charts for a finished run, with everything inline so it can be opened or attached anywhere
+pub fn page(runner: &TestRunner, host: &HostInfo) -> String {
+    let config = &runner.config;
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(out, "<title>rusty-writes results</title>\n<style>\n{}\n</style>\n</head>\n<body>", STYLE);
+    let _ = writeln!(out, "<h1>rusty-writes results</h1>\n<table>");
+    let settings = [
+        ("Started", output::timestamp(runner.started_at)),
+        ("Seed", config.seed.to_string()),
+        ("Durability", config.durability.to_string()),
+        ("Faults", config.faults.to_string()),
+        ("Device", config.device.as_ref().map_or_else(|| "none".to_string(), |device| device.to_string())),
+        ("Repetitions", config.repetitions.to_string()),
+        ("Host", host.hostname.clone()),
+        ("Kernel", host.kernel.clone()),
+        ("CPU", format!("{} ({} CPUs)", host.cpu_model, host.cpus)),
+        ("Memory", format!("{:.2} GB", host.memory_bytes as f64 / 1_000_000_000.0)),
+        ("Filesystem", host.filesystem.as_ref().map_or_else(|| "unknown".to_string(), |filesystem| filesystem.to_string())),
+    ];
+    for (name, value) in settings {
+        let _ = writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, escape(&value));
+    }
+    let _ = writeln!(out, "</table>");
+
+    // Colours go by the order strategies first ran in
+    let mut tests: Vec<&str> = Vec::new();
+    for run in &runner.test_runs {
+        if !tests.contains(&run.test.as_str()) {
+            tests.push(&run.test);
+        }
+    }
+    let color = |test: &str| COLORS[tests.iter().position(|&t| t == test).unwrap_or(0) % COLORS.len()];
+
+    for (section, strategies) in sections(&runner.test_runs) {
+        let _ = writeln!(out, "<h2>{}</h2>", escape(&section));
+        let first_runs = if config.repetitions > 1 { " (first run)" } else { "" };
+
+        let cdf: Vec<Series> = strategies
+            .iter()
+            .map(|(test, runs)| Series {
+                name: short_name(test),
+                color: color(test),
+                points: runs[0]
+                    .result
+                    .quantiles
+                    .iter()
+                    .filter(|(_, latency_ns)| *latency_ns > 0.0)
+                    .map(|&(fraction, latency_ns)| (latency_ns / 1_000_000.0, fraction))
+                    .collect(),
+            })
+            .collect();
+        let x = Axis::log("Latency ms", cdf.iter().flat_map(|series| series.points.iter().map(|point| point.0)));
+        let y = Axis { label: "Fraction of writes", log: false, min: 0.0, max: 1.0 };
+        out.push_str(&line_chart(&format!("Latency CDF{}", first_runs), &x, &y, &cdf));
+
+        let throughput: Vec<Series> = strategies
+            .iter()
+            .map(|(test, runs)| Series {
+                name: short_name(test),
+                color: color(test),
+                points: runs[0]
+                    .result
+                    .timeline
+                    .iter()
+                    .filter(|window| window.duration_ns > 0.0)
+                    .map(|window| {
+                        let middle_s = (window.start_ns + window.duration_ns / 2.0) / 1_000_000_000.0;
+                        (middle_s, window.result.num_writes as f64 / (window.duration_ns / 1_000_000_000.0))
+                    })
+                    .collect(),
+            })
+            .collect();
+        let x = Axis::linear("Time s", throughput.iter().flat_map(|series| series.points.iter().map(|point| point.0)));
+        let y = Axis::linear("Writes/s", throughput.iter().flat_map(|series| series.points.iter().map(|point| point.1)));
+        out.push_str(&line_chart(&format!("Throughput over time{}", first_runs), &x, &y, &throughput));
+
+        let queues: Vec<Series> = strategies
+            .iter()
+            .filter_map(|(test, runs)| {
+                let queue = runs[0].result.queue.as_ref()?;
+                Some(Series {
+                    name: short_name(test),
+                    color: color(test),
+                    points: queue.samples.iter().map(|sample| (sample.at_ns / 1_000_000_000.0, sample.depth as f64)).collect(),
+                })
+            })
+            .collect();
+        if !queues.is_empty() {
+            let x = Axis::linear("Time s", queues.iter().flat_map(|series| series.points.iter().map(|point| point.0)));
+            let y = Axis::linear("Messages queued", queues.iter().flat_map(|series| series.points.iter().map(|point| point.1)));
+            out.push_str(&line_chart(&format!("Queue depth over time{}", first_runs), &x, &y, &queues));
+        }
+
+        let bars: Vec<(&str, &str, f64)> = strategies
+            .iter()
+            .map(|(test, runs)| {
+                let writes_per_s: Vec<f64> = runs.iter().map(|run| run.writes_per_s()).collect();
+                (short_name(test), color(test), median(&writes_per_s))
+            })
+            .collect();
+        let title = if config.repetitions > 1 { "Writes/s (median of the repetitions)" } else { "Writes/s" };
+        out.push_str(&bar_chart(title, &bars));
+    }
+
+    if !runner.failures.is_empty() {
+        let _ = writeln!(out, "<h2>Failures</h2>\n<ul>");
+        for failure in &runner.failures {
+            let _ = writeln!(
+                out,
+                "<li>{} ({}): {}</li>",
+                escape(&failure.test),
+                escape(&failure.workload),
+                escape(&failure.error.to_string())
+            );
+        }
+        let _ = writeln!(out, "</ul>");
+    }
+    let _ = writeln!(out, "</body>\n</html>");
+    out
+}
+
+// A strategy's name and its repetitions
+type Strategy<'a> = (&'a str, Vec<&'a TestRunResult>);
+
+// Runs grouped by workload and growth policy, then by strategy, in the order each first ran
+fn sections(runs: &[TestRunResult]) -> Vec<(String, Vec<Strategy<'_>>)> {
+    let mut sections: Vec<(String, Vec<Strategy<'_>>)> = Vec::new();
+    for run in runs {
+        let mut section = format!("{}, growth {}", run.workload, run.growth);
+        if run.capacity != Capacity::Default {
+            section = format!("{}, capacity {}", section, run.capacity);
+        }
+        let strategies = match sections.iter().position(|(existing, _)| *existing == section) {
+            Some(index) => &mut sections[index].1,
+            None => {
+                sections.push((section, Vec::new()));
+                &mut sections.last_mut().expect("Section just pushed").1
+            }
+        };
+        match strategies.iter_mut().find(|(test, _)| *test == run.test) {
+            Some((_, runs)) => runs.push(run),
+            None => strategies.push((&run.test, vec![run])),
+        }
+    }
+    sections
+}
+
+fn short_name(test: &str) -> &str {
+    test.rsplit("::").next().unwrap_or(test)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+struct Series<'a> {
+    name: &'a str,
+    color: &'a str,
+    points: Vec<(f64, f64)>,
+}
+
+struct Axis {
+    label: &'static str,
+    log: bool,
+    min: f64,
+    max: f64,
+}
+
+impl Axis {
+    // Whole powers of ten either side of the values
+    fn log(label: &'static str, values: impl Iterator<Item = f64>) -> Self {
+        let (low, high) = values.filter(|value| *value > 0.0).fold((f64::MAX, 0.0_f64), |(low, high), value| (low.min(value), high.max(value)));
+        let (min, max) = match high > 0.0 {
+            true => (10f64.powf(low.log10().floor()), 10f64.powf(high.log10().ceil())),
+            false => (1.0, 10.0),
+        };
+        Self { label, log: true, min, max: if max > min { max } else { min * 10.0 } }
+    }
+
+    // From zero up to a round number past the largest value
+    fn linear(label: &'static str, values: impl Iterator<Item = f64>) -> Self {
+        let high = values.fold(0.0, f64::max);
+        let max = match high > 0.0 {
+            true => (high / linear_step(high)).ceil() * linear_step(high),
+            false => 1.0,
+        };
+        Self { label, log: false, min: 0.0, max }
+    }
+
+    // Where a value falls along an axis of this length
+    fn position(&self, value: f64, length: f64) -> f64 {
+        let fraction = match self.log {
+            true => (value.max(self.min).log10() - self.min.log10()) / (self.max.log10() - self.min.log10()),
+            false => (value - self.min) / (self.max - self.min),
+        };
+        fraction.clamp(0.0, 1.0) * length
+    }
+
+    fn ticks(&self) -> Vec<f64> {
+        match self.log {
+            true => {
+                let decades = (self.max / self.min).log10().round() as i32;
+                (0..=decades).map(|decade| self.min * 10f64.powi(decade)).collect()
+            }
+            false => {
+                let step = linear_step(self.max);
+                (0..).map(|i| self.min + i as f64 * step).take_while(|tick| *tick <= self.max + step / 1000.0).collect()
+            }
+        }
+    }
+}
+
+// A 1, 2 or 5 times power of ten step giving about five ticks
+fn linear_step(max: f64) -> f64 {
+    let rough = max / 5.0;
+    let power = 10f64.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter().map(|multiple| multiple * power).find(|step| *step >= rough).unwrap_or(power * 10.0)
+}
+
+// Rounded first, so steps like 0.1 * 3 don't print every digit
+fn format_tick(value: f64) -> String {
+    let value = (value * 1e6).round() / 1e6;
+    if value.abs() >= 1_000_000.0 {
+        format!("{}M", value / 1_000_000.0)
+    } else if value.abs() >= 10_000.0 {
+        format!("{}k", value / 1000.0)
+    } else {
+        format!("{}", value)
+    }
+}
+
+// Plot area corners, and the start of the svg with its title, gridlines and axis labels
+fn frame(title: &str, x: &Axis, y: &Axis) -> (String, f64, f64) {
+    let (plot_width, plot_height) = (WIDTH - LEFT - RIGHT, HEIGHT - TOP - BOTTOM);
+    let mut svg = String::new();
+    let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">", WIDTH, HEIGHT);
+    let _ = writeln!(svg, "<text class=\"title\" x=\"{}\" y=\"18\">{}</text>", LEFT, escape(title));
+    for tick in x.ticks() {
+        let at = LEFT + x.position(tick, plot_width);
+        let _ = writeln!(
+            svg,
+            "<line class=\"grid\" x1=\"{at:.1}\" y1=\"{}\" x2=\"{at:.1}\" y2=\"{}\"/><text x=\"{at:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            TOP,
+            TOP + plot_height,
+            TOP + plot_height + 16.0,
+            format_tick(tick)
+        );
+    }
+    for tick in y.ticks() {
+        let at = TOP + plot_height - y.position(tick, plot_height);
+        let _ = writeln!(
+            svg,
+            "<line class=\"grid\" x1=\"{}\" y1=\"{at:.1}\" x2=\"{}\" y2=\"{at:.1}\"/><text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            LEFT,
+            LEFT + plot_width,
+            LEFT - 6.0,
+            at + 4.0,
+            format_tick(tick)
+        );
+    }
+    let _ = writeln!(
+        svg,
+        "<path class=\"axis\" fill=\"none\" d=\"M{} {} V{} H{}\"/>",
+        LEFT,
+        TOP,
+        TOP + plot_height,
+        LEFT + plot_width
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}{}</text>",
+        LEFT + plot_width / 2.0,
+        HEIGHT - 8.0,
+        x.label,
+        if x.log { " (log scale)" } else { "" }
+    );
+    let _ = writeln!(
+        svg,
+        "<text transform=\"translate(16 {}) rotate(-90)\" text-anchor=\"middle\">{}</text>",
+        TOP + plot_height / 2.0,
+        y.label
+    );
+    (svg, plot_width, plot_height)
+}
+
+fn line_chart(title: &str, x: &Axis, y: &Axis, series: &[Series]) -> String {
+    let (mut svg, plot_width, plot_height) = frame(title, x, y);
+    for (index, series) in series.iter().enumerate() {
+        let points: Vec<String> = series
+            .points
+            .iter()
+            .map(|&(px, py)| format!("{:.1},{:.1}", LEFT + x.position(px, plot_width), TOP + plot_height - y.position(py, plot_height)))
+            .collect();
+        let _ = writeln!(
+            svg,
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>",
+            series.color,
+            points.join(" ")
+        );
+        let legend_y = TOP + 10.0 + index as f64 * 18.0;
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"12\" height=\"3\" fill=\"{}\"/><text x=\"{}\" y=\"{}\">{}</text>",
+            LEFT + plot_width + 16.0,
+            legend_y - 4.0,
+            series.color,
+            LEFT + plot_width + 34.0,
+            legend_y,
+            escape(series.name)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Horizontal bars, so long strategy names fit
+fn bar_chart(title: &str, bars: &[(&str, &str, f64)]) -> String {
+    let name_width = 210.0;
+    let bar_height = 24.0;
+    let plot_width = WIDTH - name_width - 100.0;
+    let height = TOP + bars.len() as f64 * bar_height + BOTTOM;
+    let axis = Axis::linear("Writes/s", bars.iter().map(|bar| bar.2));
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">", WIDTH, height);
+    let _ = writeln!(svg, "<text class=\"title\" x=\"{}\" y=\"18\">{}</text>", name_width, escape(title));
+    for tick in axis.ticks() {
+        let at = name_width + axis.position(tick, plot_width);
+        let _ = writeln!(
+            svg,
+            "<line class=\"grid\" x1=\"{at:.1}\" y1=\"{}\" x2=\"{at:.1}\" y2=\"{}\"/><text x=\"{at:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            TOP,
+            height - BOTTOM,
+            height - BOTTOM + 16.0,
+            format_tick(tick)
+        );
+    }
+    for (index, &(name, color, value)) in bars.iter().enumerate() {
+        let y = TOP + index as f64 * bar_height;
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text><rect x=\"{}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"/><text x=\"{:.1}\" y=\"{:.1}\">{:.0}</text>",
+            name_width - 8.0,
+            y + bar_height / 2.0 + 4.0,
+            escape(name),
+            name_width,
+            y + 3.0,
+            axis.position(value, plot_width),
+            bar_height - 6.0,
+            color,
+            name_width + axis.position(value, plot_width) + 6.0,
+            y + bar_height / 2.0 + 4.0,
+            value
+        );
+    }
+    let _ = writeln!(
+        svg,
+        "<path class=\"axis\" fill=\"none\" d=\"M{} {} V{} H{}\"/>",
+        name_width,
+        TOP,
+        height - BOTTOM,
+        name_width + plot_width
+    );
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>", name_width + plot_width / 2.0, height - 8.0, axis.label);
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -1,11 +1,80 @@
-use std::ops::Range;
-
-use crate::test_result::TestResult;
+use std::path::Path;
+use std::sync::Arc;
 
 use async_trait::async_trait;
+use tokio::sync::oneshot;
+
+use crate::error::Result;
+use crate::fault::ErrorPolicy;
+use crate::ordering::OrderGuarantee;
+use crate::queue::{Capacity, QueueGauge};
+use crate::record::Record;
+use crate::run_config::RunConfig;
+use crate::storage::Storage;
+
+// Why a write wasn't acked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteError {
+    // The strategy gave up on this write
+    Failed,
+    // The strategy gave up on the whole run, this write and every one after it fail
+    Aborted,
+}
+
+// Resolves once the message has been written to the file, or the strategy has given up on it
+pub type Ack = oneshot::Receiver<std::result::Result<(), WriteError>>;
+pub type AckSender = oneshot::Sender<std::result::Result<(), WriteError>>;
+
+// A write is acked once it has reached the kernel, and the disk at `synced` durability. tokio's File and a
+// BufWriter return from a write once it's buffered, so writers using them go through `ErrorHandler::append`,
+// which flushes before it returns. A failed write or final flush has already been counted against the run by the
+// error handler, so a writer has nothing more to do with the error than hand it back on the ack, and drops it if
+// the caller has gone away.
+#[async_trait]
+pub trait LogWriter: Send + Sync {
+    // Returns once the caller is free to move on, the write itself is done when the ack resolves
+    async fn write(&self, record: Record) -> Result<Ack>;
+    async fn close(self: Box<Self>) -> Result<()>;
+
+    // The queue writes wait in before reaching the file, for strategies that have one
+    fn queue(&self) -> Option<Arc<QueueGauge>> {
+        None
+    }
+}
 
 #[async_trait]
-pub trait LoadTest {
+pub trait LoadTest: Send + Sync {
     fn get_name(&self) -> &str;
-    async fn run(&self, num_writes: usize, size_range: Range<usize>) -> TestResult;
-}
\ No newline at end of file
+
+    // The order records are promised to land in the file. A single writer draining a channel writes them in the
+    // order they were sent, which is total.
+    fn ordering(&self) -> OrderGuarantee;
+
+    // What the strategy does when a write call fails
+    fn error_policy(&self) -> ErrorPolicy;
+
+    // Whether producers can be spawned as separate tasks, or writes have to be issued from a single loop
+    fn concurrent(&self) -> bool {
+        true
+    }
+
+    // Whether writes go through a channel, which `--capacity` sizes
+    fn channel(&self) -> bool {
+        false
+    }
+
+    async fn open(
+        &self,
+        file_path: &Path,
+        config: &RunConfig,
+        storage: &Storage,
+        capacity: Capacity,
+    ) -> Result<Box<dyn LogWriter>>;
+}
+
+// Ack for strategies that finish the write before returning from `write`
+pub fn acked(result: std::result::Result<(), WriteError>) -> Ack {
+    let (sender, receiver) = oneshot::channel();
+    sender.send(result).expect("Failed to send ack");
+    receiver
+}
@@ -1,3 +1,6 @@
+use std::env;
+use std::path::Path;
+use std::process;
 
 use arc_mutex_test::ArcMutexTest;
 use flume_mpmc_test::FlumeMpmcTest;
@@ -5,12 +8,38 @@ use flume_mpsc_buffered_writer_test::FlumeMpscBufferedWriterTest;
 use flume_mpsc_callback_test::FlumeMpscCallbackTest;
 use mpsc_single_writer_test::MpscSingleWriterTest;
 use sync_io_test::SyncIoTest;
+use compare::CompareOptions;
+use error::{Error, Result};
 use load_test::LoadTest;
+use output::HostInfo;
+use report::ReportOptions;
+use run_config::RunConfig;
 use test_runner::TestRunner;
 
+mod alloc;
+mod compare;
+mod crash;
+mod device;
+mod error;
+mod fault;
+mod filesystem;
+mod history;
+mod html;
 mod load_test;
+mod ordering;
+mod output;
+mod page_cache;
+mod profile;
+mod queue;
+mod record;
+mod report;
+mod resources;
+mod run_config;
+mod storage;
 mod test_result;
 mod test_runner;
+mod trace;
+mod workload;
 
 // tests
 mod sync_io_test;
@@ -21,17 +50,115 @@ mod flume_mpsc_buffered_writer_test;
 mod flume_mpsc_callback_test;
 
 
-#[tokio::main]
-async fn main() {
-    let tests: Vec<Box<dyn LoadTest>> = vec![
+// Only counts once --allocations enables it
+#[global_allocator]
+static ALLOCATOR: alloc::CountingAllocator = alloc::CountingAllocator;
+
+fn all_tests() -> Vec<Box<dyn LoadTest>> {
+    vec![
         Box::new(SyncIoTest),
         Box::new(ArcMutexTest),
         Box::new(MpscSingleWriterTest),
         Box::new(FlumeMpmcTest),
         Box::new(FlumeMpscBufferedWriterTest),
         Box::new(FlumeMpscCallbackTest),
-    ];
+    ]
+}
+
+#[tokio::main]
+async fn main() {
+    match env::args().nth(1).as_deref() {
+        Some("gen-trace") => return exit_on_error(trace::generate(env::args().skip(2))),
+        Some("read-log") => {
+            let path = exit_on_error(env::args().nth(2).ok_or_else(|| Error::argument("Usage: read-log PATH")));
+            if let Err(error) = record::dump(Path::new(&path)) {
+                eprintln!("Failed to read {}: {}", path, error);
+                process::exit(1);
+            }
+            return;
+        }
+        Some("history") => return exit_on_error(history::show(env::args().skip(2))),
+        Some("compare") => {
+            let options = exit_on_error(CompareOptions::parse(env::args().skip(2)));
+            let baseline = exit_on_error(compare::load(&options.baseline));
+            let (current, failed) = match &options.against {
+                Some(path) => (exit_on_error(compare::load(path)), false),
+                None => {
+                    let runner = run(exit_on_error(RunConfig::parse_args(options.run_args.iter().cloned()))).await;
+                    (runner.test_runs.iter().map(output::run_json).collect(), !runner.failures.is_empty())
+                }
+            };
+            let regressions = compare::compare(&baseline, &current, &options);
+            if regressions > 0 || failed {
+                process::exit(1);
+            }
+            return;
+        }
+        Some("report") => {
+            let options = exit_on_error(ReportOptions::parse(env::args().skip(2)));
+            let document = match &options.from {
+                Some(path) => exit_on_error(report::load(path)),
+                None => {
+                    let runner = run(exit_on_error(RunConfig::parse_args(options.run_args.iter().cloned()))).await;
+                    output::document(&runner, &HostInfo::detect(&runner.config))
+                }
+            };
+            let markdown = report::markdown(&document);
+            match &options.readme {
+                Some(path) => {
+                    if let Err(error) = report::rewrite(path, &markdown) {
+                        eprintln!("Failed to rewrite {}: {}", path.display(), error);
+                        process::exit(1);
+                    }
+                }
+                None => print!("{}", markdown),
+            }
+            return;
+        }
+        Some("crash") => return exit_on_error(crash::run(all_tests(), env::args().skip(2)).await),
+        Some("crash-child") => {
+            if let Err(error) = crash::child(all_tests(), env::args().skip(2)).await {
+                eprintln!("crash-child failed: {}", error);
+                process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let runner = run(exit_on_error(RunConfig::from_args())).await;
+    if !runner.failures.is_empty() {
+        process::exit(1);
+    }
+}
+
+// Prints an error that stops the program before it gets going and exits non-zero
+fn exit_on_error<T>(result: Result<T>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1)
+    })
+}
+
+// Runs every strategy and writes the results wherever the config asks for them
+async fn run(config: RunConfig) -> TestRunner {
+    if config.allocations {
+        alloc::enable();
+    }
+    let mut stdout = config.writes_to_stdout().then(output::take_stdout);
+    let mut runner = TestRunner::new(config);
+    runner.run_tests(all_tests()).await;
 
-    let runner = TestRunner::new();
-    runner.run_tests(tests).await;
+    let host = HostInfo::detect(&runner.config);
+    if let Err(error) = output::write(&runner, &host, &mut stdout) {
+        eprintln!("Failed to write results: {}", error);
+        process::exit(1);
+    }
+    if let Some(path) = &runner.config.history {
+        if let Err(error) = history::append(path, &runner, &host) {
+            eprintln!("Failed to append to the history in {}: {}", path.display(), error);
+            process::exit(1);
+        }
+    }
+    runner
 }
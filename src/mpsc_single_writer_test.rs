@@ -1,83 +1,98 @@
 use async_trait::async_trait;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc;
-use tokio::fs::File;
-use rand::{Rng, SeedableRng};
-use tempfile::tempdir;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
 use std::any::type_name;
-use std::ops::Range;
-use std::time::Instant;
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::load_test::LoadTest;
-use crate::test_result::TestResult;
+use crate::ordering::OrderGuarantee;
+use crate::error::{Error, Result};
+use crate::fault::ErrorPolicy;
+use crate::load_test::{Ack, AckSender, LoadTest, LogWriter};
+use crate::queue::{Capacity, QueueGauge};
+use crate::record::Record;
+use crate::run_config::{Durability, RunConfig};
+use crate::storage::Storage;
 
 pub struct MpscSingleWriterTest;
 
+struct MpscSingleWriter {
+    sender: mpsc::Sender<(Vec<u8>, AckSender)>,
+    writer_handle: JoinHandle<()>,
+    queue: Arc<QueueGauge>,
+}
+
 #[async_trait]
 impl LoadTest for MpscSingleWriterTest {
     fn get_name(&self) -> &str {
         type_name::<Self>()
     }
 
-    async fn run(&self, num_writes: usize, size_range: Range<usize>) -> TestResult {
-        let mut results = Vec::new();
-        let mut total_bytes: u64 = 0;
+    fn ordering(&self) -> OrderGuarantee {
+        OrderGuarantee::Total
+    }
 
-        // Create a temporary directory for the test
-        let dir = tempdir().expect("Failed to create temp directory");
-        let file_path = dir.path().join("test_log");
+    // The writer task reports each failure back on that request's ack and moves on to the next
+    fn error_policy(&self) -> ErrorPolicy {
+        ErrorPolicy::FailRequest
+    }
 
-        // Create a channel for sending messages to the file writer task
-        let (sender, mut receiver) = mpsc::channel::<(Vec<u8>, Instant)>(num_writes);
+    fn channel(&self) -> bool {
+        true
+    }
+
+    async fn open(
+        &self,
+        file_path: &Path,
+        config: &RunConfig,
+        storage: &Storage,
+        capacity: Capacity,
+    ) -> Result<Box<dyn LogWriter>> {
+        // Create a channel for sending messages to the file writer task. Tokio's channel is always bounded, but it
+        // allocates as it fills, so the most permits it takes is unbounded in effect.
+        let bound = capacity.bound(Some(config.num_writes.max(1)));
+        let (sender, mut receiver) = mpsc::channel::<(Vec<u8>, AckSender)>(bound.unwrap_or(Semaphore::MAX_PERMITS));
+        let queue = Arc::new(QueueGauge::new(bound));
 
         // Spawn the file writer task
+        let mut file = storage.create(file_path).await.map_err(Error::setup("creating the log file"))?;
+        let errors = storage.error_handler(self.error_policy());
+        let durability = config.durability;
+        let received = Arc::clone(&queue);
         let writer_handle = tokio::spawn(async move {
-            let mut file = File::create(&file_path).await.expect("Failed to create file");
-            while let Some((message, duration_start)) = receiver.recv().await {
-                file.write_all(&message).await.expect("Failed to write to file");
-                let duration = Instant::now().duration_since(duration_start);
-
-                // Add the result to the results vector
-                results.push(duration.as_nanos() as f64);
+            while let Some((message, ack)) = receiver.recv().await {
+                received.received(message.len());
+                let mut result = errors.append(&mut file, &message).await;
+                if result.is_ok() && durability == Durability::Synced {
+                    result = errors.sync_data(&file).await;
+                }
+                let _ = ack.send(result);
             }
-            results
+            let _ = errors.flush(&mut file).await;
         });
 
-        // Spawn the producer tasks
-        for _ in 0..num_writes {
-            let mut rng = rand::rngs::StdRng::from_entropy();
-            let msg_size = rng.gen_range(size_range.clone());
-            total_bytes += msg_size as u64;
-            let message = vec![0u8; msg_size];
-            let sender_clone = sender.clone();
-
-            tokio::spawn(async move {
-                let start = Instant::now();
-                sender_clone.send((message, start)).await.expect("Failed to send message");
-            });
-        }
-        // Signal no more messages will be sent
-        drop(sender);
+        Ok(Box::new(MpscSingleWriter { sender, writer_handle, queue }))
+    }
+}
 
-        // Wait for the writer task to finish
-        let mut results = writer_handle.await.expect("Writer task panicked");
+#[async_trait]
+impl LogWriter for MpscSingleWriter {
+    async fn write(&self, record: Record) -> Result<Ack> {
+        let message = record.encode();
+        let (ack, receiver) = oneshot::channel();
+        self.queue.send(message.len(), self.sender.send((message, ack))).await.map_err(|_| Error::ChannelClosed("writer"))?;
+        Ok(receiver)
+    }
 
-        dir.close().expect("Failed to delete temp directory");
+    fn queue(&self) -> Option<Arc<QueueGauge>> {
+        Some(Arc::clone(&self.queue))
+    }
 
-        // Calculate the mean, median, and p90
-        results.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let sum: f64 = results.iter().sum();
-        let mean = sum / num_writes as f64;
-        let median = results[num_writes / 2];
-        let p90 = results[(9 * num_writes) / 10];
+    async fn close(self: Box<Self>) -> Result<()> {
+        // Signal no more messages will be sent
+        drop(self.sender);
 
-        // Cast the result of the `run` method to a `TestResult` object
-        TestResult {
-            mean,
-            median,
-            p90,
-            num_writes: num_writes as u64,
-            total_bytes,
-        }
+        // Wait for the writer task to finish
+        self.writer_handle.await.map_err(Error::task)
     }
 }
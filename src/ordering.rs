@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::record::{Frame, RecordReader};
+
+// The order a strategy promises records end up in the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderGuarantee {
+    // Writes land in the order they were submitted, across all producers
+    Total,
+    // Each producer's writes land in the order it submitted them
+    PerProducer,
+    None,
+}
+
+impl fmt::Display for OrderGuarantee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OrderGuarantee::Total => "total",
+            OrderGuarantee::PerProducer => "per-producer FIFO",
+            OrderGuarantee::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// When a record's `write` call started and returned, relative to the start of the run
+#[derive(Clone, Copy, Default)]
+pub struct Submission {
+    pub start_ns: f64,
+    pub returned_ns: f64,
+}
+
+#[derive(Debug)]
+pub struct OrderingReport {
+    pub guarantee: OrderGuarantee,
+    // Records written after a record that was submitted later
+    pub total_violations: u64,
+    // The same, only counting records from the same producer
+    pub producer_violations: u64,
+}
+
+impl Default for OrderingReport {
+    fn default() -> Self {
+        Self { guarantee: OrderGuarantee::None, total_violations: 0, producer_violations: 0 }
+    }
+}
+
+impl OrderingReport {
+    // Whether the file breaks the order the strategy promised
+    pub fn is_broken(&self) -> bool {
+        match self.guarantee {
+            OrderGuarantee::Total => self.total_violations > 0,
+            OrderGuarantee::PerProducer => self.producer_violations > 0,
+            OrderGuarantee::None => false,
+        }
+    }
+}
+
+// Concurrent writes can land in either order, so a record only counts as out of order when some record
+// before it in the file was submitted after its own `write` call had already returned
+pub fn check(path: &Path, guarantee: OrderGuarantee, submissions: &[Submission]) -> std::io::Result<OrderingReport> {
+    let reader = RecordReader::open(path)?;
+    let mut report = OrderingReport { guarantee, total_violations: 0, producer_violations: 0 };
+    let mut latest_start = f64::MIN;
+    let mut latest_start_by_producer: HashMap<u32, f64> = HashMap::new();
+
+    for frame in reader {
+        let Frame::Record { record, .. } = frame? else { continue };
+        // Bad sequence numbers are already reported by verification
+        let Some(submission) = submissions.get(record.sequence as usize) else { continue };
+
+        if latest_start > submission.returned_ns {
+            report.total_violations += 1;
+        }
+        latest_start = latest_start.max(submission.start_ns);
+
+        let producer_start = latest_start_by_producer.entry(record.producer).or_insert(f64::MIN);
+        if *producer_start > submission.returned_ns {
+            report.producer_violations += 1;
+        }
+        *producer_start = producer_start.max(submission.start_ns);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    struct Case {
+        // (sequence, producer) in file order
+        records: &'static [(u64, u32)],
+        // (start, returned) of each sequence
+        submissions: &'static [(f64, f64)],
+        // Total and per-producer violations
+        violations: (u64, u64),
+    }
+
+    #[test]
+    fn counts_records_that_land_after_later_submissions() {
+        let cases = [
+            Case { records: &[], submissions: &[], violations: (0, 0) },
+            Case { records: &[(0, 0), (1, 0), (2, 0)], submissions: &[(0.0, 1.0), (2.0, 3.0), (4.0, 5.0)], violations: (0, 0) },
+            // Writes in flight at the same time can land in either order
+            Case { records: &[(1, 0), (0, 0)], submissions: &[(0.0, 10.0), (1.0, 10.0)], violations: (0, 0) },
+            // 1 was submitted after 0 returned, but landed first
+            Case { records: &[(1, 0), (0, 0)], submissions: &[(0.0, 1.0), (2.0, 3.0)], violations: (1, 1) },
+            Case { records: &[(1, 1), (0, 0)], submissions: &[(0.0, 1.0), (2.0, 3.0)], violations: (1, 0) },
+            // Only the latest start so far matters, and sequences beyond the submissions are skipped
+            Case {
+                records: &[(2, 0), (7, 0), (0, 1), (1, 0)],
+                submissions: &[(0.0, 1.0), (0.5, 1.5), (2.0, 3.0)],
+                violations: (2, 1),
+            },
+        ];
+        for case in cases {
+            let mut file = NamedTempFile::new().unwrap();
+            for &(sequence, producer) in case.records {
+                file.write_all(&Record::generated(sequence, producer, 10).encode()).unwrap();
+            }
+            let submissions: Vec<Submission> =
+                case.submissions.iter().map(|&(start_ns, returned_ns)| Submission { start_ns, returned_ns }).collect();
+            let report = check(file.path(), OrderGuarantee::Total, &submissions).unwrap();
+            let violations = (report.total_violations, report.producer_violations);
+            assert_eq!(violations, case.violations, "records {:?}", case.records);
+        }
+    }
+
+    #[test]
+    fn breaks_only_the_promised_order() {
+        let report = |guarantee| OrderingReport { guarantee, total_violations: 1, producer_violations: 0 };
+        assert!(report(OrderGuarantee::Total).is_broken());
+        assert!(!report(OrderGuarantee::PerProducer).is_broken());
+        assert!(!report(OrderGuarantee::None).is_broken());
+    }
+}
@@ -0,0 +1,480 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::filesystem::FilesystemInfo;
+use crate::html;
+use crate::page_cache;
+use crate::alloc::AllocationReport;
+use crate::queue::QueueReport;
+use crate::run_config::RunConfig;
+use crate::test_result::TestResult;
+use crate::test_runner::{TestRunResult, TestRunner};
+
+// Where a structured copy of the results goes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Stdout,
+    File(PathBuf),
+}
+
+impl Destination {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "-" => Destination::Stdout,
+            path => Destination::File(PathBuf::from(path)),
+        }
+    }
+}
+
+// Points stdout at stderr, so the human readable report stays out of the way of a document written to stdout,
+// and returns a handle on the real stdout
+pub fn take_stdout() -> File {
+    // SAFETY: only the standard descriptors are duplicated, and nothing has been printed through them yet
+    unsafe {
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            panic!("Failed to redirect stdout: {}", io::Error::last_os_error());
+        }
+        File::from_raw_fd(stdout)
+    }
+}
+
+// The machine the results came from
+pub struct HostInfo {
+    pub hostname: String,
+    pub kernel: String,
+    pub cpu_model: String,
+    pub cpus: usize,
+    pub memory_bytes: u64,
+    pub filesystem: Option<FilesystemInfo>,
+}
+
+impl HostInfo {
+    pub fn detect(config: &RunConfig) -> Self {
+        let read = |path: &str| fs::read_to_string(path).map(|text| text.trim().to_string()).unwrap_or_default();
+        let cpuinfo = read("/proc/cpuinfo");
+        let cpu_model = cpuinfo.lines().find_map(|line| line.strip_prefix("model name")?.split_once(':'));
+        Self {
+            hostname: read("/proc/sys/kernel/hostname"),
+            kernel: read("/proc/sys/kernel/osrelease"),
+            cpu_model: cpu_model.map_or_else(String::new, |(_, model)| model.trim().to_string()),
+            cpus: std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+            memory_bytes: page_cache::meminfo("MemTotal").unwrap_or(0),
+            filesystem: FilesystemInfo::detect(&config.target_dir).ok(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "hostname": self.hostname,
+            "kernel": self.kernel,
+            "cpu_model": self.cpu_model,
+            "cpus": self.cpus,
+            "memory_bytes": self.memory_bytes,
+            "filesystem": self.filesystem.as_ref().map(|filesystem| json!({
+                "fs_type": filesystem.fs_type,
+                "source": filesystem.source,
+                "mount_point": filesystem.mount_point,
+                "mount_options": filesystem.mount_options,
+                "super_options": filesystem.super_options,
+                "block_size": filesystem.block_size,
+                "total_bytes": filesystem.total_bytes,
+                "free_bytes": filesystem.free_bytes,
+            })),
+        })
+    }
+}
+
+// Writes the results of a finished run wherever the config asked for them
+pub fn write(runner: &TestRunner, host: &HostInfo, stdout: &mut Option<File>) -> io::Result<()> {
+    if let Some(destination) = &runner.config.json {
+        let document = serde_json::to_string_pretty(&document(runner, host))?;
+        write_to(destination, &format!("{}\n", document), stdout)?;
+    }
+    if let Some(destination) = &runner.config.csv {
+        write_to(destination, &csv(runner, host), stdout)?;
+    }
+    if let Some(destination) = &runner.config.html {
+        write_to(destination, &html::page(runner, host), stdout)?;
+    }
+    Ok(())
+}
+
+fn write_to(destination: &Destination, contents: &str, stdout: &mut Option<File>) -> io::Result<()> {
+    match destination {
+        Destination::File(path) => fs::write(path, contents),
+        Destination::Stdout => match stdout.as_mut() {
+            Some(stdout) => stdout.write_all(contents.as_bytes()),
+            None => io::stdout().write_all(contents.as_bytes()),
+        },
+    }
+}
+
+// One document for the whole run
+pub fn document(runner: &TestRunner, host: &HostInfo) -> Value {
+    let config = &runner.config;
+    json!({
+        "started_at": timestamp(runner.started_at),
+        "finished_at": timestamp(runner.finished_at),
+        "seed": config.seed,
+        "config": config_json(runner),
+        "host": host.to_json(),
+        "results": runner.test_runs.iter().map(run_json).collect::<Vec<_>>(),
+        "failures": runner.failures.iter().map(|failure| json!({
+            "test": failure.test,
+            "workload": failure.workload,
+            "error": failure.error.to_string(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+pub fn config_json(runner: &TestRunner) -> Value {
+    let config = &runner.config;
+    json!({
+        "num_writes": config.num_writes,
+        "size_range": [config.size_range.start, config.size_range.end],
+        "duration_s": config.duration.map(|duration| duration.as_secs_f64()),
+        "workloads": config.workloads.iter().map(|workload| runner.describe_workload(workload)).collect::<Vec<_>>(),
+        "sample_interval_ms": config.sample_interval.as_secs_f64() * 1000.0,
+        "durability": config.durability.to_string(),
+        "final_sync": config.final_sync,
+        "allocations": config.allocations,
+        "faults": config.faults.to_string(),
+        "device": config.device.as_ref().map(|device| device.to_string()),
+        "device_backing": format!("{:?}", config.device_backing).to_lowercase(),
+        "target_dir": config.target_dir,
+        "growth": config.growth.iter().map(|growth| growth.to_string()).collect::<Vec<_>>(),
+        "capacities": config.capacities.iter().map(|capacity| capacity.to_string()).collect::<Vec<_>>(),
+        "repetitions": config.repetitions,
+        "seed": config.seed,
+    })
+}
+
+pub fn run_json(run: &TestRunResult) -> Value {
+    let result = &run.result;
+    let verification = &result.verification;
+    let ordering = &result.ordering;
+    let faults = &result.faults;
+    let page_cache = &result.page_cache;
+    let resources = &result.resources;
+    json!({
+        "test": run.test,
+        "workload": run.workload,
+        "growth": run.growth.to_string(),
+        "capacity": run.capacity.to_string(),
+        "repetition": run.repetition,
+        "started_at": timestamp(run.started_at),
+        "run_duration_ns": run.run_duration_ns,
+        "writes_per_s": run.writes_per_s(),
+        "mb_per_s": result.mb_per_s(),
+        "valid": result.is_valid(),
+        "aborted": result.is_aborted(),
+        "latency": latency_json(result),
+        "quantiles": result.quantiles.iter().map(|(fraction, latency_ns)| json!({
+            "fraction": fraction,
+            "latency_ns": latency_ns,
+        })).collect::<Vec<_>>(),
+        "caller_latency": {
+            "mean_ns": result.caller.mean,
+            "median_ns": result.caller.median,
+            "p90_ns": result.caller.p90,
+            "p99_ns": result.caller.p99,
+            "max_ns": result.caller.max,
+        },
+        "timings": {
+            "setup_ns": result.timings.setup_ns,
+            "write_ns": result.timings.write_ns,
+            "drain_ns": result.timings.drain_ns,
+            "teardown_ns": result.timings.teardown_ns,
+            "analysis_ns": result.timings.analysis_ns,
+        },
+        "failed": result.failed,
+        "verification": {
+            "records": verification.records,
+            "missing": verification.missing,
+            "duplicated": verification.duplicated,
+            "unacked": verification.unacked,
+            "torn": verification.torn,
+            "corrupted": verification.corrupted,
+            "abandoned": verification.abandoned,
+        },
+        "ordering": {
+            "guarantee": ordering.guarantee.to_string(),
+            "total_violations": ordering.total_violations,
+            "producer_violations": ordering.producer_violations,
+        },
+        "faults": {
+            "short_writes": faults.short_writes,
+            "interrupted": faults.interrupted,
+            "no_space": faults.no_space,
+            "io_errors": faults.io_errors,
+            "retried": faults.retried,
+            "failed": faults.failed,
+            "aborted": faults.aborted,
+        },
+        "page_cache": {
+            "dirty_before_bytes": page_cache.before.dirty,
+            "writeback_before_bytes": page_cache.before.writeback,
+            "peak_dirty_bytes": page_cache.peak_dirty,
+            "peak_writeback_bytes": page_cache.peak_writeback,
+            "left_dirty_bytes": page_cache.left_dirty(),
+            "dirty_after_bytes": page_cache.after.dirty,
+            "after_finished_ns": page_cache.after_finished_ns,
+            "dirtied_bytes": page_cache.dirtied(),
+            "written_back_bytes": page_cache.written_back(),
+            "final_sync_ns": page_cache.final_sync_ns,
+        },
+        "resources": {
+            "user_cpu_us": resources.user.as_micros() as u64,
+            "system_cpu_us": resources.system.as_micros() as u64,
+            "cpu_us_per_write": resources.cpu_us_per_write(result.num_writes),
+            "voluntary_switches": resources.voluntary_switches,
+            "involuntary_switches": resources.involuntary_switches,
+            "read_syscalls": resources.read_syscalls,
+            "write_syscalls": resources.write_syscalls,
+            "bytes_read": resources.bytes_read,
+            "bytes_written": resources.bytes_written,
+            "bytes_per_write_syscall": resources.bytes_per_write_syscall(),
+            "peak_rss_bytes": resources.peak_rss,
+        },
+        "phases": result.phases.iter().map(|phase| json!({
+            "name": phase.name,
+            "duration_ns": phase.duration_ns,
+            "offered_rate": phase.offered_rate,
+            "acked": phase.acked,
+            "latency": latency_json(&phase.result),
+        })).collect::<Vec<_>>(),
+        "timeline": result.timeline.iter().map(|window| json!({
+            "start_ns": window.start_ns,
+            "duration_ns": window.duration_ns,
+            "latency": latency_json(&window.result),
+            "queue_depth": window.queue_depth,
+        })).collect::<Vec<_>>(),
+        "queue": result.queue.as_ref().map(|queue| json!({
+            "capacity": queue.capacity,
+            "max_depth": queue.max_depth,
+            "peak_bytes": queue.peak_bytes,
+            "send_blocked_ns": queue.blocked_ns,
+            "sends": queue.sends,
+            "samples": queue.samples.iter().map(|sample| json!({
+                "at_ns": sample.at_ns,
+                "depth": sample.depth,
+                "bytes": sample.bytes,
+            })).collect::<Vec<_>>(),
+        })),
+        "allocations": result.allocations.as_ref().map(|allocations| json!({
+            "count": allocations.allocations,
+            "bytes": allocations.allocated_bytes,
+            "peak_live_bytes": allocations.peak_live_bytes,
+            "per_write": allocations.per_write(result.num_writes),
+            "bytes_per_write": allocations.bytes_per_write(result.num_writes),
+        })),
+    })
+}
+
+// Identifies a scenario across result files
+pub fn scenario_name(result: &Value) -> String {
+    format!("{} ({})", result["test"].as_str().unwrap_or_default(), section_name(result))
+}
+
+// The workload, growth policy and channel capacity of a result, the capacity only when one was asked for
+pub fn section_name(result: &Value) -> String {
+    let field = |name: &str| result[name].as_str().unwrap_or_default().to_string();
+    let section = format!("{}, growth {}", field("workload"), field("growth"));
+    match result["capacity"].as_str() {
+        Some(capacity) if capacity != "default" => format!("{}, capacity {}", section, capacity),
+        _ => section,
+    }
+}
+
+fn latency_json(result: &TestResult) -> Value {
+    json!({
+        "num_writes": result.num_writes,
+        "total_bytes": result.total_bytes,
+        "mean_ns": result.mean,
+        "median_ns": result.median,
+        "p90_ns": result.p90,
+        "p99_ns": result.p99,
+        "max_ns": result.max,
+    })
+}
+
+// What one row of the CSV is made from
+struct Row<'a> {
+    config: &'a RunConfig,
+    host: &'a HostInfo,
+    run: &'a TestRunResult,
+}
+
+type Column = (&'static str, fn(&Row) -> String);
+
+// One row per scenario and repetition, nested figures like the timeline are only in the JSON
+const COLUMNS: &[Column] = &[
+    ("started_at", |row| timestamp(row.run.started_at)),
+    ("hostname", |row| row.host.hostname.clone()),
+    ("seed", |row| row.config.seed.to_string()),
+    ("test", |row| row.run.test.clone()),
+    ("workload", |row| row.run.workload.clone()),
+    ("growth", |row| row.run.growth.to_string()),
+    ("capacity", |row| row.run.capacity.to_string()),
+    ("repetition", |row| row.run.repetition.to_string()),
+    ("durability", |row| row.config.durability.to_string()),
+    ("final_sync", |row| row.config.final_sync.to_string()),
+    ("faults", |row| row.config.faults.to_string()),
+    ("device", |row| row.config.device.as_ref().map_or_else(String::new, |device| device.name.clone())),
+    ("run_duration_ns", |row| row.run.run_duration_ns.to_string()),
+    ("setup_ns", |row| row.run.result.timings.setup_ns.to_string()),
+    ("write_ns", |row| row.run.result.timings.write_ns.to_string()),
+    ("drain_ns", |row| row.run.result.timings.drain_ns.to_string()),
+    ("teardown_ns", |row| row.run.result.timings.teardown_ns.to_string()),
+    ("analysis_ns", |row| row.run.result.timings.analysis_ns.to_string()),
+    ("writes_per_s", |row| row.run.writes_per_s().to_string()),
+    ("mb_per_s", |row| row.run.result.mb_per_s().to_string()),
+    ("num_writes", |row| row.run.result.num_writes.to_string()),
+    ("total_bytes", |row| row.run.result.total_bytes.to_string()),
+    ("mean_ns", |row| row.run.result.mean.to_string()),
+    ("median_ns", |row| row.run.result.median.to_string()),
+    ("p90_ns", |row| row.run.result.p90.to_string()),
+    ("p99_ns", |row| row.run.result.p99.to_string()),
+    ("max_ns", |row| row.run.result.max.to_string()),
+    ("caller_mean_ns", |row| row.run.result.caller.mean.to_string()),
+    ("caller_median_ns", |row| row.run.result.caller.median.to_string()),
+    ("caller_p90_ns", |row| row.run.result.caller.p90.to_string()),
+    ("caller_p99_ns", |row| row.run.result.caller.p99.to_string()),
+    ("caller_max_ns", |row| row.run.result.caller.max.to_string()),
+    ("valid", |row| row.run.result.is_valid().to_string()),
+    ("failed", |row| row.run.result.failed.to_string()),
+    ("records", |row| row.run.result.verification.records.to_string()),
+    ("missing", |row| row.run.result.verification.missing.to_string()),
+    ("duplicated", |row| row.run.result.verification.duplicated.to_string()),
+    ("unacked", |row| row.run.result.verification.unacked.to_string()),
+    ("torn", |row| row.run.result.verification.torn.to_string()),
+    ("corrupted", |row| row.run.result.verification.corrupted.to_string()),
+    ("abandoned", |row| row.run.result.verification.abandoned.to_string()),
+    ("ordering_guarantee", |row| row.run.result.ordering.guarantee.to_string()),
+    ("ordering_total_violations", |row| row.run.result.ordering.total_violations.to_string()),
+    ("ordering_producer_violations", |row| row.run.result.ordering.producer_violations.to_string()),
+    ("short_writes", |row| row.run.result.faults.short_writes.to_string()),
+    ("interrupted", |row| row.run.result.faults.interrupted.to_string()),
+    ("no_space", |row| row.run.result.faults.no_space.to_string()),
+    ("io_errors", |row| row.run.result.faults.io_errors.to_string()),
+    ("retried", |row| row.run.result.faults.retried.to_string()),
+    ("fault_failed", |row| row.run.result.faults.failed.to_string()),
+    ("fault_aborted", |row| row.run.result.faults.aborted.to_string()),
+    ("peak_dirty_bytes", |row| row.run.result.page_cache.peak_dirty.to_string()),
+    ("peak_writeback_bytes", |row| row.run.result.page_cache.peak_writeback.to_string()),
+    ("left_dirty_bytes", |row| row.run.result.page_cache.left_dirty().to_string()),
+    ("dirtied_bytes", |row| row.run.result.page_cache.dirtied().to_string()),
+    ("written_back_bytes", |row| row.run.result.page_cache.written_back().to_string()),
+    ("final_sync_ns", |row| row.run.result.page_cache.final_sync_ns.map_or_else(String::new, |ns| ns.to_string())),
+    ("user_cpu_us", |row| row.run.result.resources.user.as_micros().to_string()),
+    ("system_cpu_us", |row| row.run.result.resources.system.as_micros().to_string()),
+    ("cpu_us_per_write", |row| row.run.result.resources.cpu_us_per_write(row.run.result.num_writes).to_string()),
+    ("voluntary_switches", |row| row.run.result.resources.voluntary_switches.to_string()),
+    ("involuntary_switches", |row| row.run.result.resources.involuntary_switches.to_string()),
+    ("read_syscalls", |row| row.run.result.resources.read_syscalls.to_string()),
+    ("write_syscalls", |row| row.run.result.resources.write_syscalls.to_string()),
+    ("bytes_per_write_syscall", |row| row.run.result.resources.bytes_per_write_syscall().to_string()),
+    ("peak_rss_bytes", |row| row.run.result.resources.peak_rss.to_string()),
+    ("queue_max_depth", |row| queue_field(row, |queue| queue.max_depth.to_string())),
+    ("queue_peak_bytes", |row| queue_field(row, |queue| queue.peak_bytes.to_string())),
+    ("send_blocked_ns", |row| queue_field(row, |queue| queue.blocked_ns.to_string())),
+    ("allocations", |row| allocation_field(row, |allocations, _| allocations.allocations.to_string())),
+    ("allocated_bytes", |row| allocation_field(row, |allocations, _| allocations.allocated_bytes.to_string())),
+    ("peak_live_bytes", |row| allocation_field(row, |allocations, _| allocations.peak_live_bytes.to_string())),
+    ("allocations_per_write", |row| allocation_field(row, |allocations, writes| allocations.per_write(writes).to_string())),
+];
+
+// Empty for strategies without a queue
+fn queue_field(row: &Row, field: fn(&QueueReport) -> String) -> String {
+    row.run.result.queue.as_ref().map_or_else(String::new, field)
+}
+
+// Empty unless allocations were counted, the field gets the number of writes too
+fn allocation_field(row: &Row, field: fn(&AllocationReport, u64) -> String) -> String {
+    let result = &row.run.result;
+    result.allocations.as_ref().map_or_else(String::new, |allocations| field(allocations, result.num_writes))
+}
+
+fn csv(runner: &TestRunner, host: &HostInfo) -> String {
+    let mut out = COLUMNS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(",");
+    out.push('\n');
+    for run in &runner.test_runs {
+        let row = Row { config: &runner.config, host, run };
+        let fields: Vec<String> = COLUMNS.iter().map(|(_, field)| csv_field(&field(&row))).collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+// Quotes fields with separators or quotes in them, workload descriptions have commas
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// RFC 3339 in UTC, e.g. 2024-05-01T12:30:00.123Z
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, time_of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+    // Days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(seconds: u64, millis: u64) -> String {
+        timestamp(UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        // Leap days, including 2000, which is a leap year despite being a century, and 2100, which isn't
+        assert_eq!(at(1_709_210_096, 789), "2024-02-29T12:34:56.789Z");
+        assert_eq!(at(951_782_400, 0), "2000-02-29T00:00:00.000Z");
+        assert_eq!(at(4_107_542_399, 0), "2100-02-28T23:59:59.000Z");
+        assert_eq!(at(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+        // Across a year boundary
+        assert_eq!(at(946_684_799, 999), "1999-12-31T23:59:59.999Z");
+        assert_eq!(at(946_684_800, 0), "2000-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn clamps_times_before_the_epoch() {
+        assert_eq!(timestamp(UNIX_EPOCH - Duration::from_secs(1)), "1970-01-01T00:00:00.000Z");
+    }
+}
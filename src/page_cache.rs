@@ -0,0 +1,168 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+// How many bytes of dirty pages the kernel lets build up before it makes writers wait for writeback
+pub fn dirty_limit() -> Option<u64> {
+    let dirty_bytes: u64 = read_number("/proc/sys/vm/dirty_bytes")?;
+    if dirty_bytes > 0 {
+        return Some(dirty_bytes);
+    }
+    // The kernel takes the ratio of dirtyable memory, available memory is close enough
+    let dirty_ratio: u64 = read_number("/proc/sys/vm/dirty_ratio")?;
+    Some(meminfo("MemAvailable")? * dirty_ratio / 100)
+}
+
+// A field of /proc/meminfo in bytes
+pub fn meminfo(field: &str) -> Option<u64> {
+    meminfo_field(&fs::read_to_string("/proc/meminfo").ok()?, field)
+}
+
+fn meminfo_field(meminfo: &str, field: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kb: u64 = value.trim().trim_end_matches(" kB").parse().ok()?;
+        Some(kb * 1024)
+    })
+}
+
+// A counter of /proc/vmstat, these are in pages
+fn vmstat_field(vmstat: &str, field: &str) -> Option<u64> {
+    vmstat.lines().find_map(|line| line.strip_prefix(field)?.strip_prefix(' ')?.trim().parse().ok())
+}
+
+fn read_number(path: &str) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// The state of the page cache across the whole system at one point, every figure is in bytes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Snapshot {
+    // Waiting to be written back
+    pub dirty: u64,
+    // Being written back right now
+    pub writeback: u64,
+    // Totals since boot of pages dirtied and pages written back
+    pub dirtied: u64,
+    pub written: u64,
+}
+
+impl Snapshot {
+    // Missing files or fields read as zero, e.g. outside Linux
+    pub fn take() -> Self {
+        let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        let vmstat = fs::read_to_string("/proc/vmstat").unwrap_or_default();
+        // SAFETY: sysconf has no preconditions
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+        Self {
+            dirty: meminfo_field(&meminfo, "Dirty").unwrap_or(0),
+            writeback: meminfo_field(&meminfo, "Writeback").unwrap_or(0),
+            dirtied: vmstat_field(&vmstat, "nr_dirtied").unwrap_or(0) * page_size,
+            written: vmstat_field(&vmstat, "nr_written").unwrap_or(0) * page_size,
+        }
+    }
+
+    // Dirty or being written back, so not on the disk yet
+    pub fn outstanding(&self) -> u64 {
+        self.dirty + self.writeback
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PageCacheReport {
+    pub before: Snapshot,
+    // Highest seen while the strategy was writing
+    pub peak_dirty: u64,
+    pub peak_writeback: u64,
+    // Once the strategy had closed its file, and synced it if asked to
+    pub finished: Snapshot,
+    // Once the file had been read back and checked, just before it was deleted
+    pub after: Snapshot,
+    pub after_finished_ns: f64,
+    // Time the final sync took, when one was asked for
+    pub final_sync_ns: Option<f64>,
+}
+
+impl PageCacheReport {
+    // Dirty data the run added that the kernel still had to write back when the strategy finished
+    pub fn left_dirty(&self) -> u64 {
+        self.finished.outstanding().saturating_sub(self.before.outstanding())
+    }
+
+    pub fn dirtied(&self) -> u64 {
+        self.finished.dirtied.saturating_sub(self.before.dirtied)
+    }
+
+    pub fn written_back(&self) -> u64 {
+        self.finished.written.saturating_sub(self.before.written)
+    }
+}
+
+// Samples the page cache in the background while a run writes
+pub struct Monitor {
+    before: Snapshot,
+    peak: Arc<Mutex<(u64, u64)>>,
+    sampler: JoinHandle<()>,
+}
+
+impl Monitor {
+    pub fn start(every: Duration) -> Self {
+        let before = Snapshot::take();
+        let peak = Arc::new(Mutex::new((before.dirty, before.writeback)));
+        let sampler = tokio::spawn({
+            let peak = Arc::clone(&peak);
+            async move {
+                let mut ticks = interval(every);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                loop {
+                    ticks.tick().await;
+                    let snapshot = Snapshot::take();
+                    let mut peak = peak.lock().expect("Peak lock poisoned");
+                    peak.0 = peak.0.max(snapshot.dirty);
+                    peak.1 = peak.1.max(snapshot.writeback);
+                }
+            }
+        });
+        Self { before, peak, sampler }
+    }
+
+    // Stops sampling once the strategy is done writing
+    pub fn finish(self, final_sync_ns: Option<f64>) -> FinishedMonitor {
+        self.sampler.abort();
+        let finished = Snapshot::take();
+        let (peak_dirty, peak_writeback) = *self.peak.lock().expect("Peak lock poisoned");
+        let report = PageCacheReport {
+            before: self.before,
+            peak_dirty: peak_dirty.max(finished.dirty),
+            peak_writeback: peak_writeback.max(finished.writeback),
+            finished,
+            final_sync_ns,
+            ..Default::default()
+        };
+        FinishedMonitor { report, finished_at: Instant::now() }
+    }
+}
+
+// A run that errors out drops its monitor early, which mustn't leave the sampler running
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.sampler.abort();
+    }
+}
+
+// Waiting for the last snapshot, taken after the file has been checked
+pub struct FinishedMonitor {
+    report: PageCacheReport,
+    finished_at: Instant,
+}
+
+impl FinishedMonitor {
+    pub fn after(mut self) -> PageCacheReport {
+        self.report.after = Snapshot::take();
+        self.report.after_finished_ns = self.finished_at.elapsed().as_nanos() as f64;
+        self.report
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug)]
+pub enum PhaseKind {
+    // Rate changes linearly from `from` to `to` over the phase
+    Ramp { from: f64, to: f64 },
+    Steady { rate: f64 },
+    // Writes at `rate` while on, nothing while off
+    Bursts { rate: f64, on: Duration, off: Duration },
+}
+
+#[derive(Clone, Debug)]
+pub struct Phase {
+    pub kind: PhaseKind,
+    pub duration: Duration,
+}
+
+impl Phase {
+    // Number of writes that should have been issued `elapsed` into the phase
+    pub fn expected_writes(&self, elapsed: Duration) -> u64 {
+        let t = elapsed.min(self.duration).as_secs_f64();
+        let writes = match self.kind {
+            PhaseKind::Ramp { from, to } => {
+                let length = self.duration.as_secs_f64();
+                from * t + (to - from) * t * t / (2.0 * length)
+            }
+            PhaseKind::Steady { rate } => rate * t,
+            PhaseKind::Bursts { rate, on, off } => {
+                let period = (on + off).as_secs_f64();
+                let periods = (t / period).floor();
+                let within = t - periods * period;
+                rate * (periods * on.as_secs_f64() + within.min(on.as_secs_f64()))
+            }
+        };
+        writes as u64
+    }
+
+    // Average offered rate over the whole phase
+    pub fn offered_rate(&self) -> f64 {
+        self.expected_writes(self.duration) as f64 / self.duration.as_secs_f64()
+    }
+
+    // Parses a phase spec, one of:
+    //   ramp:SECS:FROM-TO
+    //   steady:SECS:RATE
+    //   burst:SECS:RATE:ON_MS/OFF_MS
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let invalid = || Error::argument(format!("Invalid phase {}", spec));
+        if parts.len() < 3 {
+            return Err(invalid());
+        }
+        // A phase has to last some time and a rate can't be negative, or the expected writes make no sense
+        let secs: f64 = parts[1].parse().map_err(|_| invalid())?;
+        if !(secs > 0.0 && secs.is_finite()) {
+            return Err(Error::argument(format!("Phase {} must last longer than 0 seconds", spec)));
+        }
+        let duration = Duration::from_secs_f64(secs);
+        let rate = |value: &str| -> Result<f64> {
+            let rate: f64 = value.parse().map_err(|_| invalid())?;
+            if !(rate >= 0.0 && rate.is_finite()) {
+                return Err(Error::argument(format!("Phase {} has rate {}, it must be 0 or more", spec, value)));
+            }
+            Ok(rate)
+        };
+        let millis = |value: &str| -> Result<Duration> { Ok(Duration::from_millis(value.parse().map_err(|_| invalid())?)) };
+        let kind = match (parts[0], parts.len()) {
+            ("ramp", 3) => {
+                let (from, to) = parts[2].split_once('-').ok_or_else(invalid)?;
+                PhaseKind::Ramp { from: rate(from)?, to: rate(to)? }
+            }
+            ("steady", 3) => PhaseKind::Steady { rate: rate(parts[2])? },
+            ("burst", 4) => {
+                let (on, off) = parts[3].split_once('/').ok_or_else(invalid)?;
+                let (on, off) = (millis(on)?, millis(off)?);
+                // The bursts repeat every on + off
+                if (on + off).is_zero() {
+                    return Err(Error::argument(format!("Phase {} needs bursts longer than 0 ms", spec)));
+                }
+                PhaseKind::Bursts { rate: rate(parts[2])?, on, off }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Phase { kind, duration })
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.duration.as_secs_f64();
+        match self.kind {
+            PhaseKind::Ramp { from, to } => write!(f, "ramp {:.0}->{:.0}/s {:.1}s", from, to, secs),
+            PhaseKind::Steady { rate } => write!(f, "steady {:.0}/s {:.1}s", rate, secs),
+            PhaseKind::Bursts { rate, on, off } => {
+                write!(f, "burst {:.0}/s {}/{}ms {:.1}s", rate, on.as_millis(), off.as_millis(), secs)
+            }
+        }
+    }
+}
+
+// Ramp up, hold, burst, spike to 10x and recover, all relative to a base rate
+pub fn production(rate: f64) -> Vec<Phase> {
+    let secs = Duration::from_secs;
+    vec![
+        Phase { kind: PhaseKind::Ramp { from: 0.0, to: rate }, duration: secs(2) },
+        Phase { kind: PhaseKind::Steady { rate }, duration: secs(3) },
+        Phase {
+            kind: PhaseKind::Bursts { rate: rate * 4.0, on: Duration::from_millis(200), off: Duration::from_millis(600) },
+            duration: secs(4),
+        },
+        Phase { kind: PhaseKind::Steady { rate: rate * 10.0 }, duration: secs(1) },
+        Phase { kind: PhaseKind::Steady { rate }, duration: secs(3) },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(spec: &str) -> String {
+        Phase::parse(spec).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_phases() {
+        let ramp = Phase::parse("ramp:2:0-1000").unwrap();
+        assert!(matches!(ramp.kind, PhaseKind::Ramp { from, to } if from == 0.0 && to == 1000.0));
+        assert_eq!(ramp.duration, Duration::from_secs(2));
+
+        let steady = Phase::parse("steady:0.5:200").unwrap();
+        assert!(matches!(steady.kind, PhaseKind::Steady { rate } if rate == 200.0));
+        assert_eq!(steady.duration, Duration::from_millis(500));
+
+        let burst = Phase::parse("burst:4:800:200/600").unwrap();
+        assert_eq!(burst.to_string(), "burst 800/s 200/600ms 4.0s");
+    }
+
+    #[test]
+    fn expected_writes_follow_the_phase() {
+        assert_eq!(Phase::parse("ramp:2:0-1000").unwrap().expected_writes(Duration::from_secs(2)), 1000);
+        assert_eq!(Phase::parse("steady:3:100").unwrap().expected_writes(Duration::from_secs(10)), 300);
+        // Two full 250ms bursts and half of the third
+        assert_eq!(Phase::parse("burst:4:1000:250/750").unwrap().expected_writes(Duration::from_millis(2125)), 625);
+    }
+
+    #[test]
+    fn rejects_a_negative_rate() {
+        assert!(error("steady:2:-10").contains("must be 0 or more"));
+    }
+
+    #[test]
+    fn rejects_a_zero_duration() {
+        assert!(error("steady:0:100").contains("must last longer than 0 seconds"));
+    }
+
+    #[test]
+    fn rejects_a_zero_burst_period() {
+        assert!(error("burst:4:800:0/0").contains("needs bursts longer than 0 ms"));
+    }
+
+    #[test]
+    fn accepts_a_zero_rate_and_an_idle_gap() {
+        assert_eq!(Phase::parse("steady:1:0").unwrap().expected_writes(Duration::from_secs(1)), 0);
+        assert_eq!(Phase::parse("burst:1:1000:0/500").unwrap().expected_writes(Duration::from_secs(1)), 0);
+        assert_eq!(Phase::parse("burst:1:1000:500/0").unwrap().expected_writes(Duration::from_secs(1)), 1000);
+    }
+
+    #[test]
+    fn rejects_invalid_phases() {
+        for spec in ["steady:3", "steady:x:100", "ramp:2:1000", "burst:4:800:200", "burst:4:800", "spike:1:10", "steady:-1:100", "steady:inf:100", "burst:1:-5:100/100"] {
+            assert!(Phase::parse(spec).is_err(), "{} was accepted", spec);
+        }
+    }
+}
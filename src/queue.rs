@@ -0,0 +1,207 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+use crate::error::{self, Error};
+use crate::run_config::parse;
+
+// How big a channel strategy's queue is allowed to get
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capacity {
+    // Whatever the strategy picks for itself
+    Default,
+    Bounded(usize),
+    Unbounded,
+}
+
+impl Capacity {
+    // default, unbounded, or a number of messages. Not 0, which tokio's channel refuses and flume's turns into a
+    // rendezvous, where every send waits for the receiver.
+    pub fn parse(value: &str) -> error::Result<Self> {
+        match value {
+            "default" => Ok(Capacity::Default),
+            "unbounded" => Ok(Capacity::Unbounded),
+            capacity => match parse(capacity)? {
+                0 => Err(Error::argument("Invalid capacity 0, a bounded channel has to hold at least 1 message")),
+                capacity => Ok(Capacity::Bounded(capacity)),
+            },
+        }
+    }
+
+    // The bound to use, given the strategy's own, where None is unbounded
+    pub fn bound(self, default: Option<usize>) -> Option<usize> {
+        match self {
+            Capacity::Default => default,
+            Capacity::Bounded(capacity) => Some(capacity),
+            Capacity::Unbounded => None,
+        }
+    }
+
+    pub fn flume<T>(self, default: Option<usize>) -> (flume::Sender<T>, flume::Receiver<T>) {
+        match self.bound(default) {
+            Some(capacity) => flume::bounded(capacity),
+            None => flume::unbounded(),
+        }
+    }
+}
+
+impl fmt::Display for Capacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capacity::Default => write!(f, "default"),
+            Capacity::Bounded(capacity) => write!(f, "{}", capacity),
+            Capacity::Unbounded => write!(f, "unbounded"),
+        }
+    }
+}
+
+// What a channel strategy's queue holds, kept up to date by its senders and receivers. The counts go up once a
+// send has finished, so a receiver can briefly get ahead of them.
+pub struct QueueGauge {
+    // None for an unbounded queue
+    capacity: Option<usize>,
+    // Messages and payload bytes waiting in the queue
+    depth: AtomicI64,
+    bytes: AtomicI64,
+    max_depth: AtomicI64,
+    peak_bytes: AtomicI64,
+    // Time senders spent waiting for room in the queue, and how many sends there were
+    blocked_ns: AtomicU64,
+    sends: AtomicU64,
+}
+
+impl QueueGauge {
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            depth: AtomicI64::new(0),
+            bytes: AtomicI64::new(0),
+            max_depth: AtomicI64::new(0),
+            peak_bytes: AtomicI64::new(0),
+            blocked_ns: AtomicU64::new(0),
+            sends: AtomicU64::new(0),
+        }
+    }
+
+    // Times the send, and counts the message as queued once it's in
+    pub async fn send<T, E>(&self, bytes: usize, send: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        let started = Instant::now();
+        let result = send.await;
+        self.blocked_ns.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.sends.fetch_add(1, Ordering::Relaxed);
+        if result.is_ok() {
+            let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+            let queued = self.bytes.fetch_add(bytes as i64, Ordering::Relaxed) + bytes as i64;
+            self.max_depth.fetch_max(depth, Ordering::Relaxed);
+            self.peak_bytes.fetch_max(queued, Ordering::Relaxed);
+        }
+        result
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(bytes as i64, Ordering::Relaxed);
+    }
+
+    fn current(&self) -> (u64, u64) {
+        (self.depth.load(Ordering::Relaxed).max(0) as u64, self.bytes.load(Ordering::Relaxed).max(0) as u64)
+    }
+}
+
+// The queue at one point in the run
+#[derive(Clone, Copy, Debug)]
+pub struct QueueSample {
+    pub at_ns: f64,
+    pub depth: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct QueueReport {
+    // None for an unbounded queue
+    pub capacity: Option<usize>,
+    pub max_depth: u64,
+    // Payload bytes, the channel's own overhead isn't counted
+    pub peak_bytes: u64,
+    pub blocked_ns: f64,
+    pub sends: u64,
+    pub samples: Vec<QueueSample>,
+}
+
+impl QueueReport {
+    pub fn blocked_ns_per_send(&self) -> f64 {
+        self.blocked_ns / self.sends.max(1) as f64
+    }
+}
+
+// Samples the queue's depth in the background while a run writes, the peaks come from the gauge itself
+pub struct QueueMonitor {
+    gauge: Arc<QueueGauge>,
+    samples: Arc<Mutex<Vec<QueueSample>>>,
+    sampler: JoinHandle<()>,
+}
+
+impl QueueMonitor {
+    pub fn start(gauge: Arc<QueueGauge>, every: Duration, start: Instant) -> Self {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let sampler = tokio::spawn({
+            let gauge = Arc::clone(&gauge);
+            let samples = Arc::clone(&samples);
+            async move {
+                let mut ticks = interval(every);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                loop {
+                    ticks.tick().await;
+                    let (depth, bytes) = gauge.current();
+                    let at_ns = start.elapsed().as_nanos() as f64;
+                    samples.lock().expect("Queue samples lock poisoned").push(QueueSample { at_ns, depth, bytes });
+                }
+            }
+        });
+        Self { gauge, samples, sampler }
+    }
+
+    pub fn finish(self) -> QueueReport {
+        self.sampler.abort();
+        let gauge = &self.gauge;
+        QueueReport {
+            capacity: gauge.capacity,
+            max_depth: gauge.max_depth.load(Ordering::Relaxed).max(0) as u64,
+            peak_bytes: gauge.peak_bytes.load(Ordering::Relaxed).max(0) as u64,
+            blocked_ns: gauge.blocked_ns.load(Ordering::Relaxed) as f64,
+            sends: gauge.sends.load(Ordering::Relaxed),
+            samples: std::mem::take(&mut *self.samples.lock().expect("Queue samples lock poisoned")),
+        }
+    }
+}
+
+// A run that errors out drops its monitor early, which mustn't leave the sampler running
+impl Drop for QueueMonitor {
+    fn drop(&mut self) {
+        self.sampler.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_capacities() {
+        assert_eq!(Capacity::parse("default").unwrap(), Capacity::Default);
+        assert_eq!(Capacity::parse("unbounded").unwrap(), Capacity::Unbounded);
+        assert_eq!(Capacity::parse("64").unwrap(), Capacity::Bounded(64));
+        assert_eq!(Capacity::parse("1").unwrap().bound(Some(5)), Some(1));
+        assert_eq!(Capacity::Default.bound(Some(5)), Some(5));
+    }
+
+    #[test]
+    fn rejects_a_capacity_of_0() {
+        assert!(Capacity::parse("0").unwrap_err().to_string().contains("Invalid capacity 0"));
+    }
+}
@@ -0,0 +1,463 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// On-disk record format, all fields little endian:
+//
+//   length       u32  payload length in bytes
+//   crc          u32  CRC32C of everything after this field, starting with the length
+//   sequence     u64  position of the record in submission order
+//   timestamp    u64  microseconds since the unix epoch when the record was created
+//   producer     u32  id of the client that submitted the record
+//   payload      [u8; length]
+//
+// There is no file header or padding, records are written back to back. A preallocated file may end in zeros,
+// which never decode as a record, past the last one.
+pub const HEADER_LEN: usize = 28;
+const MAX_PAYLOAD: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub sequence: u64,
+    pub timestamp_us: u64,
+    pub producer: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn new(sequence: u64, producer: u32, payload: Vec<u8>) -> Self {
+        let timestamp_us = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_micros() as u64);
+        Self { sequence, timestamp_us, producer, payload }
+    }
+
+    // A record with a `size` byte payload derived from the sequence number
+    pub fn generated(sequence: u64, producer: u32, size: usize) -> Self {
+        let mut payload = Vec::with_capacity(size);
+
+        // Cheap pseudo random fill so misplaced or mixed up bytes don't look like valid payloads
+        let mut state = sequence.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        for _ in 0..size {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            payload.push(state as u8);
+        }
+        Self::new(sequence, producer, payload)
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        buf.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&self.sequence.to_le_bytes());
+        buf.extend_from_slice(&self.timestamp_us.to_le_bytes());
+        buf.extend_from_slice(&self.producer.to_le_bytes());
+        buf.extend_from_slice(&self.payload);
+
+        let crc = crc(&buf);
+        buf[4..8].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+}
+
+fn crc(frame: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(&frame[0..4]);
+    crc32c::crc32c_append(crc, &frame[8..])
+}
+
+// What the reader found at a given offset in the file
+#[derive(Debug)]
+pub enum Frame {
+    Record { offset: u64, record: Record },
+    // A stretch of bytes that doesn't decode as a record, the reader skips to the next valid one
+    Corrupted { offset: u64, len: u64 },
+    // The file ends part way through a record
+    Torn { offset: u64, len: u64 },
+}
+
+enum Decoded {
+    Record(Record),
+    // Not a record, bad length or checksum
+    Invalid,
+    // The file ends before the record would
+    Incomplete,
+}
+
+// Iterates the frames of a log file in order. A read error is handed back in place of a frame and ends the
+// iteration, since nothing after it can be trusted.
+pub struct RecordReader {
+    file: File,
+    buf: Vec<u8>,
+    pos: usize,
+    offset: u64,
+    eof: bool,
+    failed: bool,
+    torn_tail: Option<(u64, u64)>,
+}
+
+impl RecordReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: File::open(path)?,
+            buf: Vec::new(),
+            pos: 0,
+            offset: 0,
+            eof: false,
+            failed: false,
+            torn_tail: None,
+        })
+    }
+
+    // Whether `len` bytes are buffered at the current position, reading more if needed
+    fn has(&mut self, len: usize) -> io::Result<bool> {
+        while self.remaining() < len && !self.eof {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+            let start = self.buf.len();
+            self.buf.resize(start + len.max(1 << 20), 0);
+            let read = self.file.read(&mut self.buf[start..]);
+            self.buf.truncate(start + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(read) => self.eof = read == 0,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(self.remaining() >= len)
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.pos += len;
+        self.offset += len as u64;
+    }
+
+    // Skips a run of zeros at least a header long, since no record starts with one. Returns whether the
+    // zeros went on to the end of the file, which makes them preallocated space rather than damage. A record's
+    // length can start with zero bytes, so the last zeros before any data are left to be decoded.
+    fn skip_zeros(&mut self) -> io::Result<bool> {
+        self.has(HEADER_LEN)?;
+        let header_len = self.remaining().min(HEADER_LEN);
+        if header_len == 0 || self.buf[self.pos..self.pos + header_len].iter().any(|byte| *byte != 0) {
+            return Ok(false);
+        }
+        loop {
+            let more = self.has(HEADER_LEN)?;
+            let zeros = self.buf[self.pos..].iter().take_while(|byte| **byte == 0).count();
+            if zeros < self.remaining() {
+                self.advance(zeros.saturating_sub(HEADER_LEN - 1));
+                return Ok(false);
+            }
+            if !more {
+                self.advance(zeros);
+                return Ok(true);
+            }
+            self.advance(zeros - (HEADER_LEN - 1));
+        }
+    }
+
+    // Nothing valid follows `end`, so the file is torn from the first record that ran past it
+    fn end_of_file(&mut self, start: u64, incomplete_at: Option<u64>, end: u64) -> Option<Frame> {
+        let tail = incomplete_at.unwrap_or(end);
+        if tail > start {
+            if end > tail {
+                self.torn_tail = Some((tail, end - tail));
+            }
+            return Some(Frame::Corrupted { offset: start, len: tail - start });
+        }
+        (end > start).then_some(Frame::Torn { offset: start, len: end - start })
+    }
+
+    fn decode(&mut self) -> io::Result<Decoded> {
+        if !self.has(HEADER_LEN)? {
+            return Ok(Decoded::Incomplete);
+        }
+        let header = &self.buf[self.pos..self.pos + HEADER_LEN];
+        let payload_len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        if payload_len > MAX_PAYLOAD {
+            return Ok(Decoded::Invalid);
+        }
+        if !self.has(HEADER_LEN + payload_len)? {
+            return Ok(Decoded::Incomplete);
+        }
+
+        let frame = &self.buf[self.pos..self.pos + HEADER_LEN + payload_len];
+        if crc(frame) != u32::from_le_bytes(frame[4..8].try_into().unwrap()) {
+            return Ok(Decoded::Invalid);
+        }
+        Ok(Decoded::Record(Record {
+            sequence: u64::from_le_bytes(frame[8..16].try_into().unwrap()),
+            timestamp_us: u64::from_le_bytes(frame[16..24].try_into().unwrap()),
+            producer: u32::from_le_bytes(frame[24..28].try_into().unwrap()),
+            payload: frame[HEADER_LEN..].to_vec(),
+        }))
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        if let Some((offset, len)) = self.torn_tail.take() {
+            return Ok(Some(Frame::Torn { offset, len }));
+        }
+
+        let start = self.offset;
+        let mut incomplete_at = None;
+        loop {
+            let zeros_at = self.offset;
+            if self.skip_zeros()? {
+                return Ok(self.end_of_file(start, incomplete_at, zeros_at));
+            }
+            match self.decode()? {
+                Decoded::Record(record) if self.offset == start => {
+                    self.advance(record.encoded_len());
+                    return Ok(Some(Frame::Record { offset: start, record }));
+                }
+                // Hand back the bad stretch first, the record after it comes on the next call
+                Decoded::Record(_) => return Ok(Some(Frame::Corrupted { offset: start, len: self.offset - start })),
+                // Skip ahead a byte at a time until the next record starts
+                Decoded::Invalid => self.advance(1),
+                Decoded::Incomplete if self.remaining() > 0 => {
+                    // Either a record cut short or garbage claiming a long length, keep looking for a valid one
+                    incomplete_at.get_or_insert(self.offset);
+                    self.advance(1);
+                }
+                Decoded::Incomplete => return Ok(self.end_of_file(start, incomplete_at, self.offset)),
+            }
+        }
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<io::Result<Frame>> {
+        if self.failed {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.failed = matches!(frame, Some(Err(_)));
+        frame
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub records: u64,
+    pub missing: u64,
+    pub duplicated: u64,
+    // Records whose write failed but that made it into the file anyway
+    pub unacked: u64,
+    // Record cut short by the end of the file
+    pub torn: u64,
+    // Bad checksums, unknown sequence numbers and stretches of bytes that aren't a record
+    pub corrupted: u64,
+    // Torn or corrupted stretches that lie wholly within what failed requests left behind
+    pub abandoned: u64,
+}
+
+impl VerifyReport {
+    pub fn is_valid(&self) -> bool {
+        self.missing == 0 && self.duplicated == 0 && self.torn == 0 && self.corrupted == 0
+    }
+}
+
+// Scans the file for the records 0..acked.len(), each acked one should appear exactly once. Damage within the
+// `abandoned` ranges was left by failed requests, which the strategy has already reported.
+pub fn verify(path: &Path, acked: &[bool], abandoned: &[Range<u64>]) -> io::Result<VerifyReport> {
+    let reader = RecordReader::open(path)?;
+    let mut seen = vec![false; acked.len()];
+    let mut report = VerifyReport::default();
+    let abandoned = merge(abandoned);
+    let is_abandoned = |offset: u64, len: u64| {
+        abandoned.iter().any(|range| range.start <= offset && offset + len <= range.end)
+    };
+
+    for frame in reader {
+        match frame? {
+            Frame::Record { record, .. } => match seen.get_mut(record.sequence as usize) {
+                Some(true) => report.duplicated += 1,
+                Some(seen) => {
+                    *seen = true;
+                    report.records += 1;
+                    if !acked[record.sequence as usize] {
+                        report.unacked += 1;
+                    }
+                }
+                None => report.corrupted += 1,
+            },
+            Frame::Corrupted { offset, len } | Frame::Torn { offset, len } if is_abandoned(offset, len) => {
+                report.abandoned += 1
+            }
+            Frame::Corrupted { .. } => report.corrupted += 1,
+            Frame::Torn { .. } => report.torn += 1,
+        }
+    }
+
+    report.missing = seen.iter().zip(acked).filter(|(seen, acked)| **acked && !**seen).count() as u64;
+    Ok(report)
+}
+
+// Sorted, with overlapping and touching ranges joined, since one bad stretch can span several failed requests
+fn merge(ranges: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::new();
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// read-log: prints every frame in a log file followed by a summary
+pub fn dump(path: &Path) -> io::Result<()> {
+    let reader = RecordReader::open(path)?;
+    let (mut records, mut bytes, mut bad) = (0u64, 0u64, 0u64);
+    println!("{:>14} {:>12} {:>18} {:>10} {:>10}", "Offset", "Sequence", "Timestamp us", "Producer", "Length");
+    for frame in reader {
+        match frame? {
+            Frame::Record { offset, record } => {
+                println!(
+                    "{:>14} {:>12} {:>18} {:>10} {:>10}",
+                    offset,
+                    record.sequence,
+                    record.timestamp_us,
+                    record.producer,
+                    record.payload.len()
+                );
+                records += 1;
+                bytes += record.encoded_len() as u64;
+            }
+            Frame::Corrupted { offset, len } => {
+                println!("{:>14} corrupted {} bytes", offset, len);
+                bad += 1;
+            }
+            Frame::Torn { offset, len } => {
+                println!("{:>14} torn record, {} bytes to the end of the file", offset, len);
+                bad += 1;
+            }
+        }
+    }
+    println!();
+    println!("{} records ({} bytes), {} corrupted or torn", records, bytes, bad);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // 100 bytes once encoded
+    const SIZE: usize = 100 - HEADER_LEN;
+
+    // Records 0..count with `size` byte payloads, back to back
+    fn log(count: u64, size: usize) -> (Vec<Record>, Vec<u8>) {
+        let records: Vec<Record> = (0..count).map(|sequence| Record::generated(sequence, 1, size)).collect();
+        let bytes = records.iter().flat_map(Record::encode).collect();
+        (records, bytes)
+    }
+
+    fn file(bytes: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    fn frames(bytes: &[u8]) -> Vec<Frame> {
+        let file = file(bytes);
+        RecordReader::open(file.path()).unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn reads_back_what_was_encoded() {
+        let (records, bytes) = log(10, SIZE);
+        let read: Vec<Record> = frames(&bytes)
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::Record { record, .. } => record,
+                frame => panic!("unexpected {:?}", frame),
+            })
+            .collect();
+        assert_eq!(read, records);
+
+        let report = verify(file(&bytes).path(), &[true; 10], &[]).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.records, 10);
+    }
+
+    #[test]
+    fn skips_zeros() {
+        // A 512 byte payload puts a zero byte at the start of each record's length
+        let (_, mut bytes) = log(3, 512);
+        let mut padded = vec![0; 100];
+        padded.append(&mut bytes);
+        padded.resize(padded.len() + 4096, 0);
+
+        // Zeros running to the end are preallocated space
+        let report = verify(file(&padded[100..]).path(), &[true; 3], &[]).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.records, 3);
+
+        // Zeros with records after them are damage, but the records still decode
+        let report = verify(file(&padded).path(), &[true; 3], &[]).unwrap();
+        assert_eq!((report.records, report.corrupted, report.torn), (3, 1, 0));
+    }
+
+    #[test]
+    fn detects_a_torn_tail() {
+        let (_, bytes) = log(5, SIZE);
+        let report = verify(file(&bytes[..bytes.len() - 30]).path(), &[true; 5], &[]).unwrap();
+        assert_eq!((report.records, report.torn, report.missing), (4, 1, 1));
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn detects_corrupted_bytes() {
+        let (_, mut bytes) = log(5, SIZE);
+        bytes[250] ^= 0xff;
+        let report = verify(file(&bytes).path(), &[true; 5], &[]).unwrap();
+        assert_eq!((report.records, report.corrupted, report.missing), (4, 1, 1));
+
+        match &frames(&bytes)[2] {
+            Frame::Corrupted { offset, len } => assert_eq!((*offset, *len), (200, 100)),
+            frame => panic!("unexpected {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn counts_unacked_and_duplicated_records() {
+        let (records, mut bytes) = log(3, SIZE);
+        bytes.extend(records[1].encode());
+        let report = verify(file(&bytes).path(), &[true, true, false], &[]).unwrap();
+        assert_eq!((report.records, report.duplicated, report.unacked), (3, 1, 1));
+    }
+
+    #[test]
+    fn excludes_damage_left_by_failed_requests() {
+        let (_, bytes) = log(5, SIZE);
+        let torn = &bytes[..bytes.len() - 30];
+        let report = verify(file(torn).path(), &[true, true, true, true, false], &[400..450, 450..500]).unwrap();
+        assert_eq!((report.torn, report.abandoned), (0, 1));
+        assert!(report.is_valid());
+
+        // Only damage wholly within what failed requests left is excused
+        let report = verify(file(torn).path(), &[true, true, true, true, false], &[420..450, 450..500]).unwrap();
+        assert_eq!((report.torn, report.abandoned), (1, 0));
+    }
+
+    #[test]
+    fn merges_touching_ranges() {
+        assert_eq!(merge(&[10..20, 0..5, 5..8, 15..30, 40..50]), vec![0..8, 10..30, 40..50]);
+    }
+}
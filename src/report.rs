@@ -0,0 +1,220 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::compare::{median, METRICS};
+use crate::error::{Error, Result};
+use crate::output;
+
+// Marks the section of a Markdown file the report replaces
+pub const START_MARKER: &str = "<!-- results:start -->";
+pub const END_MARKER: &str = "<!-- results:end -->";
+
+pub struct ReportOptions {
+    // A document written with --json to report on instead of running the scenarios now
+    pub from: Option<PathBuf>,
+    // Markdown file whose marked section gets replaced with the report, printed to stdout otherwise
+    pub readme: Option<PathBuf>,
+    // Everything else, passed on to the run
+    pub run_args: Vec<String>,
+}
+
+impl ReportOptions {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self { from: None, readme: None, run_args: Vec::new() };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+            match arg.as_str() {
+                "--from" => options.from = Some(PathBuf::from(value()?)),
+                "--readme" => options.readme = Some(PathBuf::from(value()?)),
+                _ => options.run_args.push(arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Value> {
+    let text = fs::read_to_string(path).map_err(Error::setup("reading the results"))?;
+    serde_json::from_str(&text).map_err(|e| {
+        Error::invalid("reading the results", format!("{} isn't a results document: {}", path.display(), e))
+    })
+}
+
+// A Markdown summary of a results document: the config and environment it ran with, then a table per workload
+// with each strategy's rank on every metric
+pub fn markdown(document: &Value) -> String {
+    let config = &document["config"];
+    let host = &document["host"];
+    let text = |value: &Value| match value {
+        Value::String(text) => text.clone(),
+        Value::Array(values) => values.iter().map(|value| value.as_str().unwrap_or_default()).collect::<Vec<_>>().join(", "),
+        Value::Null => "none".to_string(),
+        value => value.to_string(),
+    };
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "Generated by `cargo run --release -- report` from a run started at {}, seed {}.\n",
+        text(&document["started_at"]),
+        text(&document["seed"])
+    );
+    let _ = writeln!(out, "| Setting | Value |\n| --- | --- |");
+    let settings = [
+        ("Workloads", text(&config["workloads"])),
+        ("Durability", text(&config["durability"])),
+        ("Final sync", if config["final_sync"].as_bool() == Some(true) { "yes" } else { "no" }.to_string()),
+        ("Growth", text(&config["growth"])),
+        ("Channel capacity", text(&config["capacities"])),
+        ("Faults", text(&config["faults"])),
+        ("Device", text(&config["device"])),
+        ("Repetitions", text(&config["repetitions"])),
+    ];
+    for (name, value) in settings {
+        let _ = writeln!(out, "| {} | {} |", name, value);
+    }
+
+    let _ = writeln!(out, "\n| Environment | |\n| --- | --- |");
+    let filesystem = &host["filesystem"];
+    let environment = [
+        ("Kernel", text(&host["kernel"])),
+        ("CPU", format!("{} ({} CPUs)", text(&host["cpu_model"]), text(&host["cpus"]))),
+        ("Memory", format!("{:.2} GB", host["memory_bytes"].as_f64().unwrap_or(0.0) / 1_000_000_000.0)),
+        (
+            "Filesystem",
+            match filesystem {
+                Value::Null => "unknown".to_string(),
+                _ => format!(
+                    "{} on {} ({}), {} byte blocks",
+                    text(&filesystem["fs_type"]),
+                    text(&filesystem["source"]),
+                    text(&filesystem["mount_options"]),
+                    text(&filesystem["block_size"])
+                ),
+            },
+        ),
+    ];
+    for (name, value) in environment {
+        let _ = writeln!(out, "| {} | {} |", name, value);
+    }
+
+    for (section, strategies) in sections(document["results"].as_array().map_or(&[], Vec::as_slice)) {
+        let _ = writeln!(out, "\n#### {}\n", section);
+        let labels: Vec<&str> = METRICS.iter().map(|metric| metric.label).collect();
+        let _ = writeln!(out, "| Strategy | {} |", labels.join(" | "));
+        let _ = writeln!(out, "| --- |{}", " ---: |".repeat(METRICS.len()));
+
+        // Median of the repetitions for every metric of every strategy
+        let values: Vec<Vec<Option<f64>>> = strategies
+            .iter()
+            .map(|(_, runs)| {
+                METRICS
+                    .iter()
+                    .map(|metric| {
+                        let values: Vec<f64> = runs.iter().filter_map(|run| (metric.value)(run)).collect();
+                        (!values.is_empty()).then(|| median(&values))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Best throughput first
+        let mut order: Vec<usize> = (0..strategies.len()).collect();
+        order.sort_by(|&a, &b| values[b][0].unwrap_or(0.0).total_cmp(&values[a][0].unwrap_or(0.0)));
+        for index in order {
+            let (test, runs) = &strategies[index];
+            let name = test.rsplit("::").next().unwrap_or(test);
+            let invalid = runs.iter().any(|run| run["valid"].as_bool() == Some(false));
+            let mut row = format!("| {}{} |", name, if invalid { " (invalid)" } else { "" });
+            for (column, metric) in METRICS.iter().enumerate() {
+                let Some(value) = values[index][column] else {
+                    row.push_str(" - |");
+                    continue;
+                };
+                // 1 is the best, ties share a rank
+                let better = values
+                    .iter()
+                    .filter_map(|other| other[column])
+                    .filter(|&other| if metric.higher_is_better { other > value } else { other < value })
+                    .count();
+                let _ = write!(row, " {} ({}) |", format_value(value), better + 1);
+            }
+            let _ = writeln!(out, "{}", row);
+        }
+    }
+
+    let failures = document["failures"].as_array().map_or(&[][..], Vec::as_slice);
+    if !failures.is_empty() {
+        let _ = writeln!(out, "\nFailed:\n");
+        for failure in failures {
+            let _ = writeln!(
+                out,
+                "- {} ({}): {}",
+                text(&failure["test"]),
+                text(&failure["workload"]),
+                text(&failure["error"])
+            );
+        }
+    }
+    let repetitions = config["repetitions"].as_u64().unwrap_or(1);
+    if repetitions > 1 {
+        let _ = writeln!(out, "\nEach figure is the median of {} repetitions, with the strategy's rank in parentheses.", repetitions);
+    } else {
+        let _ = writeln!(out, "\nEach figure has the strategy's rank in parentheses.");
+    }
+    out
+}
+
+// Latencies under a millisecond need more places to tell apart
+fn format_value(value: f64) -> String {
+    if value < 1.0 {
+        format!("{:.4}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+// A strategy's name and its repetitions
+type Strategy<'a> = (String, Vec<&'a Value>);
+
+// Results grouped by workload and growth policy, then by strategy, in the order each first appears
+fn sections(results: &[Value]) -> Vec<(String, Vec<Strategy<'_>>)> {
+    let mut sections: Vec<(String, Vec<Strategy>)> = Vec::new();
+    for result in results {
+        let section = output::section_name(result);
+        let strategies = match sections.iter_mut().position(|(existing, _)| *existing == section) {
+            Some(index) => &mut sections[index].1,
+            None => {
+                sections.push((section, Vec::new()));
+                &mut sections.last_mut().expect("Section just pushed").1
+            }
+        };
+        let test = result["test"].as_str().unwrap_or_default().to_string();
+        match strategies.iter_mut().find(|(existing, _)| *existing == test) {
+            Some((_, runs)) => runs.push(result),
+            None => strategies.push((test, vec![result])),
+        }
+    }
+    sections
+}
+
+// Replaces whatever is between the markers in a Markdown file with the report. The markers have to be on lines of
+// their own, so text that mentions them is left alone.
+pub fn rewrite(path: &Path, report: &str) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let marker = |marker: &str, from: usize| lines.iter().skip(from).position(|line| line.trim() == marker).map(|at| at + from);
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{} in {}", message, path.display()));
+    let start = marker(START_MARKER, 0).ok_or_else(|| invalid(format!("no {} line", START_MARKER)))?;
+    let end = marker(END_MARKER, start).ok_or_else(|| invalid(format!("no {} line after the start marker", END_MARKER)))?;
+
+    let mut rewritten: Vec<&str> = lines[..=start].to_vec();
+    rewritten.extend(["", report.trim(), ""]);
+    rewritten.extend(&lines[end..]);
+    fs::write(path, rewritten.join("\n") + "\n")
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::mem::MaybeUninit;
+use std::time::Duration;
+
+use crate::device;
+
+// What the process has used so far, from getrusage and /proc/self/io
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    user: Duration,
+    system: Duration,
+    voluntary_switches: u64,
+    involuntary_switches: u64,
+    read_syscalls: u64,
+    write_syscalls: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl Usage {
+    fn take() -> Self {
+        let mut usage = MaybeUninit::<libc::rusage>::uninit();
+        // SAFETY: `usage` is only read after getrusage has filled it in
+        let rusage = match unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } {
+            0 => unsafe { usage.assume_init() },
+            _ => return Self::default(),
+        };
+        let io = fs::read_to_string("/proc/self/io").unwrap_or_default();
+        let io_field = |name: &str| read_field(&io, name).unwrap_or(0);
+        // The simulated device's timer thread stands in for the device, so it isn't counted against the strategy
+        let device = device::timer_thread().map(Self::thread).unwrap_or_default();
+        Self {
+            user: timeval(rusage.ru_utime).saturating_sub(device.user),
+            system: timeval(rusage.ru_stime).saturating_sub(device.system),
+            voluntary_switches: (rusage.ru_nvcsw as u64).saturating_sub(device.voluntary_switches),
+            involuntary_switches: (rusage.ru_nivcsw as u64).saturating_sub(device.involuntary_switches),
+            read_syscalls: io_field("syscr"),
+            write_syscalls: io_field("syscw"),
+            bytes_read: io_field("rchar"),
+            bytes_written: io_field("wchar"),
+        }
+    }
+
+    // CPU time and context switches of one of this process's threads, getrusage only reports on the caller's
+    fn thread(tid: libc::pid_t) -> Self {
+        let stat = fs::read_to_string(format!("/proc/self/task/{}/stat", tid)).unwrap_or_default();
+        // The thread's name can hold spaces, so count fields from the paren that closes it. utime and stime
+        // are the 14th and 15th fields, in clock ticks.
+        let fields: Vec<&str> = stat.rsplit_once(')').map_or(Vec::new(), |(_, rest)| rest.split_whitespace().collect());
+        let ticks = |index: usize| fields.get(index).and_then(|field| field.parse::<u64>().ok()).unwrap_or(0);
+        // SAFETY: sysconf only reads a system setting
+        let ticks_per_s = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+        let status = fs::read_to_string(format!("/proc/self/task/{}/status", tid)).unwrap_or_default();
+        Self {
+            user: Duration::from_secs_f64(ticks(11) as f64 / ticks_per_s),
+            system: Duration::from_secs_f64(ticks(12) as f64 / ticks_per_s),
+            voluntary_switches: read_field(&status, "voluntary_ctxt_switches").unwrap_or(0),
+            involuntary_switches: read_field(&status, "nonvoluntary_ctxt_switches").unwrap_or(0),
+            ..Self::default()
+        }
+    }
+}
+
+// What a run used, every strategy runs in this process so it's the difference across the run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceReport {
+    pub user: Duration,
+    pub system: Duration,
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+    pub read_syscalls: u64,
+    pub write_syscalls: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    // Peak resident memory during the run, or of the whole process if the peak couldn't be reset
+    pub peak_rss: u64,
+}
+
+impl ResourceReport {
+    pub fn cpu(&self) -> Duration {
+        self.user + self.system
+    }
+
+    pub fn cpu_us_per_write(&self, writes: u64) -> f64 {
+        self.cpu().as_secs_f64() * 1_000_000.0 / writes.max(1) as f64
+    }
+
+    pub fn bytes_per_write_syscall(&self) -> f64 {
+        self.bytes_written as f64 / self.write_syscalls.max(1) as f64
+    }
+}
+
+// Counts what the process uses between `start` and `finish`
+pub struct Accounting {
+    start: Usage,
+}
+
+impl Accounting {
+    pub fn start() -> Self {
+        // Writing 5 to clear_refs resets the peak RSS the kernel reports, so each run gets its own
+        let _ = fs::write("/proc/self/clear_refs", "5");
+        Self { start: Usage::take() }
+    }
+
+    pub fn finish(self) -> ResourceReport {
+        let end = Usage::take();
+        let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+        ResourceReport {
+            user: end.user.saturating_sub(self.start.user),
+            system: end.system.saturating_sub(self.start.system),
+            voluntary_switches: end.voluntary_switches.saturating_sub(self.start.voluntary_switches),
+            involuntary_switches: end.involuntary_switches.saturating_sub(self.start.involuntary_switches),
+            read_syscalls: end.read_syscalls.saturating_sub(self.start.read_syscalls),
+            write_syscalls: end.write_syscalls.saturating_sub(self.start.write_syscalls),
+            bytes_read: end.bytes_read.saturating_sub(self.start.bytes_read),
+            bytes_written: end.bytes_written.saturating_sub(self.start.bytes_written),
+            // VmHWM is in kB
+            peak_rss: read_field(&status, "VmHWM").unwrap_or(0) * 1024,
+        }
+    }
+}
+
+// A `name: value` line of a /proc file, ignoring any unit after the value
+fn read_field(text: &str, name: &str) -> Option<u64> {
+    text.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(':')?.split_whitespace().next()?.parse().ok())
+}
+
+fn timeval(time: libc::timeval) -> Duration {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+}
@@ -1,4 +1,213 @@
-struct RunConfig {
-    load_test: LoadTest,
-    
-}
\ No newline at end of file
+use std::env;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::device::DeviceModel;
+use crate::error::{Error, Result};
+use crate::fault::FaultConfig;
+use crate::output::Destination;
+use crate::profile::{self, Phase};
+use crate::queue::Capacity;
+use crate::storage::{Backing, GrowthPolicy};
+use crate::trace::Trace;
+
+#[derive(Clone, Debug)]
+pub enum Workload {
+    // Spawn a producer for every write up front
+    OpenLoop,
+    // A fixed number of clients each waiting for their ack before sending the next write
+    ClosedLoop { clients: usize },
+    // Open loop at the rates set by each phase in turn
+    Profile(Vec<Phase>),
+    // Replays a recorded trace, with its timing scaled by `speed`
+    Trace { trace: Arc<Trace>, speed: f64 },
+}
+
+// When a write is acked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    // Once the write call returns and the data is in the page cache
+    Written,
+    // Once the data has been flushed to the device with fdatasync
+    Synced,
+}
+
+impl Durability {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "written" => Ok(Durability::Written),
+            "synced" => Ok(Durability::Synced),
+            _ => Err(Error::argument(format!("Invalid durability {}, expected written or synced", value))),
+        }
+    }
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Durability::Written => write!(f, "written"),
+            Durability::Synced => write!(f, "synced"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+    pub num_writes: usize,
+    pub size_range: Range<usize>,
+    pub duration: Option<Duration>,
+    pub workloads: Vec<Workload>,
+    // Width of the windows the run is broken down into over time
+    pub sample_interval: Duration,
+    pub durability: Durability,
+    // Sync the file once the strategy closes it, before the run counts as finished
+    pub final_sync: bool,
+    // Faults injected under every strategy's file
+    pub faults: FaultConfig,
+    // Simulated device written to instead of the disk
+    pub device: Option<DeviceModel>,
+    pub device_backing: Backing,
+    // Where each run's log file goes, in a fresh directory of its own
+    pub target_dir: PathBuf,
+    // Every workload is run once with each of these, to compare them
+    pub growth: Vec<GrowthPolicy>,
+    // Channel strategies are run once with each of these queue capacities
+    pub capacities: Vec<Capacity>,
+    // Count heap allocations during each run
+    pub allocations: bool,
+    // Times each scenario is run
+    pub repetitions: usize,
+    // Seeds the write sizes, so a run can be repeated with the same ones
+    pub seed: u64,
+    pub json: Option<Destination>,
+    pub csv: Option<Destination>,
+    // Standalone page of charts
+    pub html: Option<Destination>,
+    // JSON Lines file every result is appended to
+    pub history: Option<PathBuf>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            // num_writes: 1_000_000,
+            num_writes: 100_000,
+            size_range: 300..2_000,
+            duration: None,
+            workloads: vec![Workload::OpenLoop],
+            sample_interval: Duration::from_millis(100),
+            durability: Durability::Written,
+            final_sync: false,
+            faults: FaultConfig::default(),
+            device: None,
+            device_backing: Backing::Memory,
+            target_dir: env::temp_dir(),
+            growth: vec![GrowthPolicy::None],
+            capacities: vec![Capacity::Default],
+            allocations: false,
+            repetitions: 1,
+            seed: rand::random(),
+            json: None,
+            csv: None,
+            html: None,
+            history: None,
+        }
+    }
+}
+
+impl RunConfig {
+    pub fn from_args() -> Result<Self> {
+        Self::parse_args(env::args().skip(1))
+    }
+
+    pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut trace = None;
+        let mut speed: f64 = 1.0;
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+            match arg.as_str() {
+                "--writes" => config.num_writes = parse(&value()?)?,
+                "--size" => config.size_range = parse_range(&value()?)?,
+                "--duration" => {
+                    let value = value()?;
+                    let duration = Duration::try_from_secs_f64(parse(&value)?);
+                    config.duration = Some(duration.map_err(|_| Error::argument(format!("Invalid duration {}", value)))?);
+                }
+                // Comma separated list of client counts to sweep, e.g. 1,2,4,8
+                "--clients" => {
+                    config.workloads = value()?
+                        .split(',')
+                        .map(|clients| Ok(Workload::ClosedLoop { clients: parse(clients)? }))
+                        .collect::<Result<_>>()?;
+                }
+                // Comma separated list of phases, see `Phase::parse`
+                "--phases" => {
+                    let phases = value()?.split(',').map(Phase::parse).collect::<Result<_>>()?;
+                    config.workloads = vec![Workload::Profile(phases)];
+                }
+                // Preset profile around a base rate in writes/s
+                "--profile" => config.workloads = vec![Workload::Profile(profile::production(parse(&value()?)?))],
+                "--sample-interval" => config.sample_interval = Duration::from_millis(parse(&value()?)?),
+                "--durability" => config.durability = Durability::parse(&value()?)?,
+                "--final-sync" => config.final_sync = true,
+                // Comma separated list of faults, see `FaultConfig::parse`
+                "--faults" => config.faults = FaultConfig::parse(&value()?)?,
+                // A device preset with optional overrides, see `DeviceModel::parse`
+                "--device" => config.device = Some(DeviceModel::parse(&value()?)?),
+                "--device-backing" => config.device_backing = Backing::parse(&value()?)?,
+                "--target-dir" => config.target_dir = PathBuf::from(value()?),
+                // Comma separated list of growth policies, see `GrowthPolicy::parse`
+                "--growth" => config.growth = value()?.split(',').map(GrowthPolicy::parse).collect::<Result<_>>()?,
+                "--capacity" => config.capacities = value()?.split(',').map(Capacity::parse).collect::<Result<_>>()?,
+                "--allocations" => config.allocations = true,
+                "--repeat" => config.repetitions = parse::<usize>(&value()?)?.max(1),
+                "--seed" => config.seed = parse(&value()?)?,
+                // A file path, or - for stdout
+                "--json" => config.json = Some(Destination::parse(&value()?)),
+                "--csv" => config.csv = Some(Destination::parse(&value()?)),
+                "--html" => config.html = Some(Destination::parse(&value()?)),
+                "--history" => config.history = Some(PathBuf::from(value()?)),
+                "--trace" => trace = Some(Arc::new(Trace::load(Path::new(&value()?))?)),
+                "--speed" => {
+                    speed = parse(&value()?)?;
+                    // Replay scales elapsed time by the speed, which has to be a finite multiple to ever reach a record
+                    if !(speed > 0.0 && speed.is_finite()) {
+                        return Err(Error::argument(format!("--speed {} must be a number greater than 0", speed)));
+                    }
+                }
+                _ => return Err(Error::argument(format!("Unknown argument {}", arg))),
+            }
+        }
+        if let Some(trace) = trace {
+            config.workloads = vec![Workload::Trace { trace, speed }];
+        }
+        let timed_workload = |w: &Workload| matches!(w, Workload::OpenLoop | Workload::ClosedLoop { .. });
+        if config.duration.is_some() && !config.workloads.iter().all(timed_workload) {
+            return Err(Error::argument("--duration doesn't apply to profiles or traces, they run for their own length"));
+        }
+        if config.duration.is_some() && config.growth.contains(&GrowthPolicy::Fallocate(None)) {
+            return Err(Error::argument(
+                "A duration based run doesn't know how much it will write, give fallocate a size, e.g. fallocate:512",
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn writes_to_stdout(&self) -> bool {
+        [&self.json, &self.csv, &self.html].into_iter().any(|destination| matches!(destination, Some(Destination::Stdout)))
+    }
+}
+
+pub fn parse<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value.parse().map_err(|_| Error::argument(format!("Invalid value {}", value)))
+}
+
+pub fn parse_range(value: &str) -> Result<Range<usize>> {
+    let (start, end) = value.split_once("..").ok_or_else(|| Error::argument(format!("Invalid range {}", value)))?;
+    Ok(parse(start)?..parse(end)?)
+}
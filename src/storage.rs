@@ -0,0 +1,498 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::prelude::FileExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, BufWriter};
+use tokio::runtime::Handle;
+use tokio::time::{sleep, Sleep};
+
+use crate::device::Device;
+use crate::error::{Error, Result};
+use crate::fault::{ErrorHandler, ErrorPolicy, FaultInjector, FaultReport, FaultStats, Injected};
+use crate::run_config::{parse, RunConfig};
+
+// Where the bytes of a simulated device end up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backing {
+    // Kept in memory and written out to the log file after the run for verification
+    Memory,
+    // Written to the log file as they go, the device model supplies the timing
+    File,
+}
+
+impl Backing {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "memory" => Ok(Backing::Memory),
+            "file" => Ok(Backing::File),
+            _ => Err(Error::argument(format!("Invalid device backing {}, expected memory or file", value))),
+        }
+    }
+}
+
+// How the log file's blocks get allocated as it grows. Only applies to real files, a simulated device has
+// no blocks to allocate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    // Every append that passes the end of the file allocates its own blocks
+    None,
+    // Allocate this many bytes before the run starts, the run's expected size if not given
+    Fallocate(Option<u64>),
+    // Allocate this many bytes at a time, ahead of the write offset
+    Extents(u64),
+    // Set the file size up front, without allocating any blocks
+    Truncate(u64),
+}
+
+impl GrowthPolicy {
+    // none, fallocate, fallocate:MB, extent:MB or truncate:MB
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, megabytes) = match spec.split_once(':') {
+            Some((name, megabytes)) => (name, Some(parse::<u64>(megabytes)? * 1_000_000)),
+            None => (spec, None),
+        };
+        let size = || {
+            megabytes.ok_or_else(|| Error::argument(format!("Growth policy {} needs a size in MB, e.g. {}:64", name, name)))
+        };
+        match name {
+            "none" => Ok(GrowthPolicy::None),
+            "fallocate" => Ok(GrowthPolicy::Fallocate(megabytes)),
+            "extent" => Ok(GrowthPolicy::Extents(size()?.max(1))),
+            "truncate" => Ok(GrowthPolicy::Truncate(size()?)),
+            _ => Err(Error::argument(format!(
+                "Invalid growth policy {}, expected none, fallocate, extent:MB or truncate:MB",
+                spec
+            ))),
+        }
+    }
+
+    // Fills in the size of a fallocate policy that didn't give one
+    pub fn resolve(&self, expected_bytes: Option<u64>) -> Self {
+        match self {
+            GrowthPolicy::Fallocate(None) => GrowthPolicy::Fallocate(expected_bytes),
+            policy => policy.clone(),
+        }
+    }
+}
+
+impl fmt::Display for GrowthPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let megabytes = |bytes: &u64| *bytes as f64 / 1_000_000.0;
+        match self {
+            GrowthPolicy::None => write!(f, "none"),
+            GrowthPolicy::Fallocate(None) => write!(f, "fallocate"),
+            GrowthPolicy::Fallocate(Some(bytes)) => write!(f, "fallocate {:.0} MB", megabytes(bytes)),
+            GrowthPolicy::Extents(bytes) => write!(f, "{:.0} MB extents", megabytes(bytes)),
+            GrowthPolicy::Truncate(bytes) => write!(f, "truncate to {:.0} MB", megabytes(bytes)),
+        }
+    }
+}
+
+// Carries out a growth policy for every handle to the log file
+struct Growth {
+    policy: GrowthPolicy,
+    // How far the file has been allocated, None until it's first opened
+    allocated: Mutex<Option<u64>>,
+}
+
+impl Growth {
+    // Sets the file up on the first open, later ones share what it did
+    fn prepare(&self, fd: RawFd) -> io::Result<()> {
+        let mut allocated = self.allocated.lock().expect("Growth lock poisoned");
+        if allocated.is_some() {
+            return Ok(());
+        }
+        let size = match self.policy {
+            GrowthPolicy::Fallocate(Some(size)) => {
+                fallocate(fd, 0, size)?;
+                size
+            }
+            GrowthPolicy::Truncate(size) => {
+                // SAFETY: ftruncate only changes the size of the file behind a descriptor we hold open
+                if unsafe { libc::ftruncate(fd, size as libc::off_t) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                0
+            }
+            _ => 0,
+        };
+        *allocated = Some(size);
+        Ok(())
+    }
+
+    // Allocates the next extents before a write that would run past them. The write that crosses into a new
+    // extent pays for it, the same as it would with a real log segment.
+    fn reserve(&self, fd: RawFd, end: u64) -> io::Result<()> {
+        let GrowthPolicy::Extents(extent) = self.policy else {
+            return Ok(());
+        };
+        let mut allocated = self.allocated.lock().expect("Growth lock poisoned");
+        let current = allocated.unwrap_or(0);
+        if end <= current {
+            return Ok(());
+        }
+        let target = end.div_ceil(extent) * extent;
+        fallocate(fd, current, target - current)?;
+        *allocated = Some(target);
+        Ok(())
+    }
+}
+
+fn fallocate(fd: RawFd, offset: u64, len: u64) -> io::Result<()> {
+    // SAFETY: fallocate only allocates blocks for a descriptor we hold open
+    if unsafe { libc::fallocate(fd, 0, offset as libc::off_t, len as libc::off_t) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The simulated device a run writes to instead of the disk, and what holds its bytes
+#[derive(Clone)]
+struct Simulation {
+    device: Arc<Device>,
+    memory: Option<Arc<Mutex<Vec<u8>>>>,
+}
+
+impl Simulation {
+    fn medium(&self, path: &Path, truncate: bool) -> io::Result<Medium> {
+        match &self.memory {
+            Some(memory) => {
+                if truncate {
+                    memory.lock().expect("Memory lock poisoned").clear();
+                }
+                Ok(Medium::Memory(Arc::clone(memory)))
+            }
+            None => {
+                let file = std::fs::OpenOptions::new().create(true).write(true).truncate(truncate).open(path)?;
+                Ok(Medium::File(Arc::new(file)))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Medium {
+    Memory(Arc<Mutex<Vec<u8>>>),
+    // Writes only go as far as the page cache, so they don't add to the modelled time
+    File(Arc<std::fs::File>),
+}
+
+impl Medium {
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        match self {
+            Medium::Memory(memory) => {
+                let mut memory = memory.lock().expect("Memory lock poisoned");
+                let end = offset as usize + buf.len();
+                if memory.len() < end {
+                    memory.resize(end, 0);
+                }
+                memory[offset as usize..end].copy_from_slice(buf);
+                Ok(buf.len())
+            }
+            Medium::File(file) => file.write_at(buf, offset),
+        }
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        match self {
+            Medium::Memory(_) => Ok(()),
+            Medium::File(file) => file.sync_data(),
+        }
+    }
+}
+
+// Where strategies get their files from, so faults can be injected and the device simulated underneath them
+#[derive(Clone)]
+pub struct Storage {
+    faults: Arc<FaultInjector>,
+    stats: Arc<FaultStats>,
+    simulation: Option<Simulation>,
+    growth: Arc<Growth>,
+}
+
+impl Storage {
+    pub fn new(config: &RunConfig, growth: &GrowthPolicy) -> Self {
+        let stats = Arc::new(FaultStats::default());
+        let simulation = config.device.as_ref().map(|model| Simulation {
+            device: Arc::new(Device::new(model)),
+            memory: (config.device_backing == Backing::Memory).then(Default::default),
+        });
+        let growth = Arc::new(Growth { policy: growth.clone(), allocated: Mutex::new(None) });
+        Self { faults: Arc::new(FaultInjector::new(&config.faults, Arc::clone(&stats))), stats, simulation, growth }
+    }
+
+    // Creates or truncates the log file for appending
+    pub async fn create(&self, path: &Path) -> io::Result<LogFile> {
+        let target = match &self.simulation {
+            Some(simulation) => Target::Simulated {
+                device: Arc::clone(&simulation.device),
+                medium: simulation.medium(path, true)?,
+                in_flight: None,
+            },
+            None => {
+                let file = File::create(path).await?;
+                self.growth.prepare(file.as_raw_fd())?;
+                Target::File(file)
+            }
+        };
+        Ok(LogFile {
+            target,
+            faults: Arc::clone(&self.faults),
+            growth: Arc::clone(&self.growth),
+            offset: 0,
+            delay: None,
+            pending: None,
+        })
+    }
+
+    // Opens the log file for writes at explicit offsets, creating it if needed
+    pub fn open_positional(&self, path: &Path) -> io::Result<PositionalFile> {
+        let target = match &self.simulation {
+            Some(simulation) => PositionalTarget::Simulated {
+                device: Arc::clone(&simulation.device),
+                medium: simulation.medium(path, false)?,
+            },
+            None => {
+                let file = std::fs::OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+                self.growth.prepare(file.as_raw_fd())?;
+                PositionalTarget::File(file)
+            }
+        };
+        Ok(PositionalFile { target, faults: Arc::clone(&self.faults), growth: Arc::clone(&self.growth) })
+    }
+
+    // Puts the log file on disk once the run is over, so it can be read back
+    pub fn finish(&self, path: &Path) -> io::Result<()> {
+        match self.simulation.as_ref().and_then(|simulation| simulation.memory.as_ref()) {
+            Some(memory) => std::fs::write(path, &*memory.lock().expect("Memory lock poisoned")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn error_handler(&self, policy: ErrorPolicy) -> ErrorHandler {
+        ErrorHandler::new(policy, Arc::clone(&self.stats))
+    }
+
+    pub fn fault_report(&self) -> FaultReport {
+        self.stats.report()
+    }
+
+    // Byte ranges left behind by failed requests
+    pub fn abandoned(&self) -> Vec<Range<u64>> {
+        self.stats.abandoned()
+    }
+}
+
+// Where the next byte handed to a writer will land in the file
+pub trait Position {
+    fn position(&self) -> u64;
+}
+
+impl Position for LogFile {
+    fn position(&self) -> u64 {
+        self.offset
+    }
+}
+
+// Buffered bytes go out in order, so they land right after what's already in the file
+impl Position for BufWriter<LogFile> {
+    fn position(&self) -> u64 {
+        self.get_ref().offset + self.buffer().len() as u64
+    }
+}
+
+type WriteFuture = Pin<Box<dyn Future<Output = io::Result<usize>> + Send + Sync>>;
+
+enum Target {
+    File(File),
+    Simulated { device: Arc<Device>, medium: Medium, in_flight: Option<WriteFuture> },
+}
+
+// An append only file with faults injected into its write calls
+pub struct LogFile {
+    target: Target,
+    faults: Arc<FaultInjector>,
+    growth: Arc<Growth>,
+    // Where the next write lands
+    offset: u64,
+    delay: Option<Pin<Box<Sleep>>>,
+    // Decided when a write call starts and kept until the file finishes it
+    pending: Option<Injected>,
+}
+
+impl LogFile {
+    pub async fn sync_data(&self) -> io::Result<()> {
+        match &self.target {
+            Target::File(file) => file.sync_data().await,
+            Target::Simulated { device, medium, .. } => {
+                device.sync().await;
+                medium.sync_data()
+            }
+        }
+    }
+}
+
+impl AsyncWrite for LogFile {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.pending.is_none() {
+            this.pending = Some(this.faults.next(this.offset, buf.len()));
+            let latency = this.faults.latency();
+            if !latency.is_zero() {
+                this.delay = Some(Box::pin(sleep(latency)));
+            }
+        }
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+
+        let len = match this.pending.take() {
+            Some(Injected::Error(error)) => return Poll::Ready(Err(error)),
+            Some(Injected::Short(len)) => {
+                this.pending = Some(Injected::Short(len));
+                len.min(buf.len())
+            }
+            _ => {
+                this.pending = Some(Injected::Pass);
+                buf.len()
+            }
+        };
+        let result = ready!(match &mut this.target {
+            Target::File(file) => match this.growth.reserve(file.as_raw_fd(), this.offset + len as u64) {
+                Ok(()) => Pin::new(file).poll_write(cx, &buf[..len]),
+                Err(error) => Poll::Ready(Err(error)),
+            },
+            Target::Simulated { device, medium, in_flight } => {
+                let write = in_flight.get_or_insert_with(|| {
+                    let (device, medium, data, offset) = (Arc::clone(device), medium.clone(), buf[..len].to_vec(), this.offset);
+                    Box::pin(async move {
+                        device.write(data.len()).await;
+                        medium.write_at(&data, offset)
+                    })
+                });
+                let result = ready!(write.as_mut().poll(cx));
+                *in_flight = None;
+                Poll::Ready(result)
+            }
+        });
+        this.pending = None;
+        if let Ok(written) = result {
+            this.offset += written as u64;
+        }
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.target {
+            Target::File(file) => Pin::new(file).poll_flush(cx),
+            // Simulated writes are done by the time they return
+            Target::Simulated { .. } => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.target {
+            Target::File(file) => Pin::new(file).poll_shutdown(cx),
+            Target::Simulated { .. } => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+enum PositionalTarget {
+    File(std::fs::File),
+    Simulated { device: Arc<Device>, medium: Medium },
+}
+
+// A blocking file for pwrite style writes, with faults injected the same way. Only used from blocking
+// threads, which is where simulated writes wait on the runtime.
+pub struct PositionalFile {
+    target: PositionalTarget,
+    faults: Arc<FaultInjector>,
+    growth: Arc<Growth>,
+}
+
+impl PositionalFile {
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let latency = self.faults.latency();
+        if !latency.is_zero() {
+            std::thread::sleep(latency);
+        }
+        let len = match self.faults.next(offset, buf.len()) {
+            Injected::Pass => buf.len(),
+            Injected::Short(len) => len,
+            Injected::Error(error) => return Err(error),
+        };
+        match &self.target {
+            PositionalTarget::File(file) => {
+                self.growth.reserve(file.as_raw_fd(), offset + len as u64)?;
+                file.write_at(&buf[..len], offset)
+            }
+            PositionalTarget::Simulated { device, medium } => {
+                Handle::current().block_on(device.write(len));
+                medium.write_at(&buf[..len], offset)
+            }
+        }
+    }
+
+    pub fn sync_data(&self) -> io::Result<()> {
+        match &self.target {
+            PositionalTarget::File(file) => file.sync_data(),
+            PositionalTarget::Simulated { device, medium } => {
+                Handle::current().block_on(device.sync());
+                medium.sync_data()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(spec: &str) -> GrowthPolicy {
+        GrowthPolicy::parse(spec).unwrap()
+    }
+
+    fn error(spec: &str) -> String {
+        GrowthPolicy::parse(spec).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_growth_policies() {
+        assert_eq!(policy("none"), GrowthPolicy::None);
+        assert_eq!(policy("fallocate"), GrowthPolicy::Fallocate(None));
+        assert_eq!(policy("fallocate:64"), GrowthPolicy::Fallocate(Some(64_000_000)));
+        assert_eq!(policy("extent:8"), GrowthPolicy::Extents(8_000_000));
+        assert_eq!(policy("truncate:128"), GrowthPolicy::Truncate(128_000_000));
+    }
+
+    #[test]
+    fn resolves_fallocate_to_the_expected_size() {
+        assert_eq!(policy("fallocate").resolve(Some(5)), GrowthPolicy::Fallocate(Some(5)));
+        assert_eq!(policy("fallocate:1").resolve(Some(5)), GrowthPolicy::Fallocate(Some(1_000_000)));
+    }
+
+    #[test]
+    fn rejects_an_unknown_growth_policy() {
+        assert!(error("mmap").contains("Invalid growth policy"));
+    }
+
+    #[test]
+    fn rejects_an_extent_without_a_size() {
+        assert!(error("extent").contains("needs a size in MB"));
+    }
+
+    #[test]
+    fn rejects_a_size_that_isnt_a_number() {
+        assert!(error("truncate:big").contains("Invalid value"));
+    }
+}
@@ -1,63 +1,75 @@
 use async_trait::async_trait;
-use tokio::io::AsyncWriteExt;
-use tokio::fs::File;
-use rand::{Rng, SeedableRng};
-use tempfile::tempdir;
+use tokio::sync::Mutex as AsyncMutex;
 use std::any::type_name;
-use std::ops::Range;
-use std::time::Instant;
+use std::path::Path;
 
-use crate::load_test::LoadTest;
-use crate::test_result::TestResult;
+use crate::error::{Error, Result};
+use crate::fault::{ErrorHandler, ErrorPolicy};
+use crate::ordering::OrderGuarantee;
+use crate::queue::Capacity;
+use crate::load_test::{acked, Ack, LoadTest, LogWriter};
+use crate::record::Record;
+use crate::run_config::{Durability, RunConfig};
+use crate::storage::{LogFile, Storage};
 
 pub struct SyncIoTest;
 
+struct SyncIoWriter {
+    // Only ever locked by one producer at a time since writes are issued from a single loop
+    file: AsyncMutex<LogFile>,
+    errors: ErrorHandler,
+    durability: Durability,
+}
+
 #[async_trait]
 impl LoadTest for SyncIoTest {
     fn get_name(&self) -> &str {
         type_name::<Self>()
     }
 
-    async fn run(&self, num_writes: usize, size_range: Range<usize>) -> TestResult {
-        let mut results = Vec::new();
-        let mut rng = rand::rngs::StdRng::from_entropy();
-        let mut total_bytes: u64 = 0;
-
-        // Create a temporary directory for the test
-        let dir = tempdir().expect("Failed to create temp directory");
-        let file_path = dir.path().join("test_log");
-        let mut file = File::create(&file_path).await.expect("Failed to create file");
-        for _ in 0..num_writes {
-            // Generate a random message
-            let msg_size = rng.gen_range(size_range.clone());
-            total_bytes += msg_size as u64;
-            let message = vec![0u8; msg_size];
-
-            // Write the message to a file using async IO
-            let start = Instant::now();
-
-            file.write_all(&message).await.expect("Failed to write to file");
-            let duration = Instant::now().duration_since(start);
-
-            // Add the result to the results vector
-            results.push(duration.as_nanos() as f64);
-        }
-        dir.close().expect("Failed to delete temp directory");
-
-        // Calculate the mean, median, and p90
-        results.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let sum: f64 = results.iter().sum();
-        let mean = sum / num_writes as f64;
-        let median = results[num_writes / 2];
-        let p90 = results[(9 * num_writes) / 10];
-
-        // Cast the result of the `run` method to a `TestResult` object
-        TestResult {
-            mean,
-            median,
-            p90,
-            num_writes: num_writes as u64,
-            total_bytes,
+    // Writes are issued one at a time from a single loop
+    fn ordering(&self) -> OrderGuarantee {
+        OrderGuarantee::Total
+    }
+
+    // Nothing else is waiting on a single loop, so it can afford to try again
+    fn error_policy(&self) -> ErrorPolicy {
+        ErrorPolicy::Retry
+    }
+
+    fn concurrent(&self) -> bool {
+        false
+    }
+
+    async fn open(
+        &self,
+        file_path: &Path,
+        config: &RunConfig,
+        storage: &Storage,
+        _capacity: Capacity,
+    ) -> Result<Box<dyn LogWriter>> {
+        let file = storage.create(file_path).await.map_err(Error::setup("creating the log file"))?;
+        let errors = storage.error_handler(self.error_policy());
+        Ok(Box::new(SyncIoWriter { file: AsyncMutex::new(file), errors, durability: config.durability }))
+    }
+}
+
+#[async_trait]
+impl LogWriter for SyncIoWriter {
+    async fn write(&self, record: Record) -> Result<Ack> {
+        let message = record.encode();
+        // Write the message to a file using async IO
+        let mut file = self.file.lock().await;
+        let mut result = self.errors.append(&mut *file, &message).await;
+        if result.is_ok() && self.durability == Durability::Synced {
+            result = self.errors.sync_data(&file).await;
         }
+        Ok(acked(result))
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
+        let mut file = self.file.into_inner();
+        let _ = self.errors.flush(&mut file).await;
+        Ok(())
     }
 }
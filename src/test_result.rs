@@ -1,7 +1,160 @@
+use crate::fault::FaultReport;
+use crate::ordering::OrderingReport;
+use crate::page_cache::PageCacheReport;
+use crate::alloc::AllocationReport;
+use crate::queue::QueueReport;
+use crate::record::VerifyReport;
+use crate::resources::ResourceReport;
+
+#[derive(Default)]
 pub struct TestResult {
+    // Durability latency, from submitting the write until it was acked as written
     pub mean: f64,
     pub median: f64,
     pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    // Caller-visible latency, from submitting the write until `write` returned and the producer could move on
+    pub caller: LatencySummary,
     pub num_writes: u64,
     pub total_bytes: u64,
-}
\ No newline at end of file
+    // (fraction, latency ns) points along the latency CDF, closer together in the tail
+    pub quantiles: Vec<(f64, f64)>,
+    pub phases: Vec<PhaseResult>,
+    pub timeline: Vec<WindowResult>,
+    pub verification: VerifyReport,
+    pub ordering: OrderingReport,
+    // Writes the strategy gave up on, none of the other figures include them
+    pub failed: u64,
+    pub faults: FaultReport,
+    pub page_cache: PageCacheReport,
+    // What the process used while the strategy was writing
+    pub resources: ResourceReport,
+    pub timings: RunTimings,
+    // Only for strategies that queue writes on a channel
+    pub queue: Option<QueueReport>,
+    // Only with --allocations
+    pub allocations: Option<AllocationReport>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencySummary {
+    pub mean: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    pub fn from_latencies(mut latencies: Vec<f64>) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort_by(f64::total_cmp);
+        Self::from_sorted(&latencies)
+    }
+
+    fn from_sorted(sorted: &[f64]) -> Self {
+        let count = sorted.len();
+        Self {
+            mean: sorted.iter().sum::<f64>() / count as f64,
+            median: sorted[count / 2],
+            p90: sorted[(9 * count) / 10],
+            p99: sorted[(99 * count) / 100],
+            max: sorted[count - 1],
+        }
+    }
+}
+
+// Where the time of a run went
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunTimings {
+    // Creating the temp directory and opening the strategy's file
+    pub setup_ns: f64,
+    // Until every producer was done and every write acked
+    pub write_ns: f64,
+    // Closing the strategy, which flushes whatever it still buffered, and the final sync if asked for
+    pub drain_ns: f64,
+    // Reading the file back to verify it, and deleting it
+    pub teardown_ns: f64,
+    // Sorting the latencies and summarising them
+    pub analysis_ns: f64,
+}
+
+impl RunTimings {
+    // Throughput only counts the time spent getting the writes out
+    pub fn throughput_ns(&self) -> f64 {
+        self.write_ns + self.drain_ns
+    }
+}
+
+pub struct PhaseResult {
+    pub name: String,
+    pub duration_ns: f64,
+    pub offered_rate: f64,
+    // Acks that arrived while the phase was running, for any write
+    pub acked: u64,
+    // Latencies of the writes submitted during the phase
+    pub result: TestResult,
+}
+
+pub struct WindowResult {
+    pub start_ns: f64,
+    pub duration_ns: f64,
+    // Writes acked during the window
+    pub result: TestResult,
+    // Deepest the strategy's queue was sampled at during the window
+    pub queue_depth: Option<u64>,
+}
+
+impl TestResult {
+    // A run whose file is missing records or breaks the strategy's ordering promise doesn't count
+    pub fn is_valid(&self) -> bool {
+        self.verification.is_valid() && !self.ordering.is_broken()
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.faults.aborted > 0
+    }
+
+    pub fn writes_per_s(&self) -> f64 {
+        self.num_writes as f64 / (self.timings.throughput_ns() / 1_000_000_000.0)
+    }
+
+    pub fn mb_per_s(&self) -> f64 {
+        self.total_bytes as f64 / 1_000_000.0 / (self.timings.throughput_ns() / 1_000_000_000.0)
+    }
+
+    pub fn from_latencies(mut results: Vec<f64>, total_bytes: u64) -> Self {
+        let num_writes = results.len();
+        if num_writes == 0 {
+            return Self { total_bytes, ..Default::default() };
+        }
+
+        // Calculate the mean, median, p90, p99 and max
+        results.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let LatencySummary { mean, median, p90, p99, max } = LatencySummary::from_sorted(&results);
+
+        // Every percentile, then finer steps through the last one
+        let mut fractions: Vec<f64> = (0..=100).map(|percent| percent as f64 / 100.0).collect();
+        fractions.extend([0.995, 0.999, 0.9995, 0.9999]);
+        fractions.sort_by(f64::total_cmp);
+        let quantiles = fractions
+            .into_iter()
+            .map(|fraction| (fraction, results[((num_writes - 1) as f64 * fraction).round() as usize]))
+            .collect();
+
+        TestResult {
+            mean,
+            median,
+            p90,
+            p99,
+            max,
+            num_writes: num_writes as u64,
+            total_bytes,
+            quantiles,
+            ..Default::default()
+        }
+    }
+}
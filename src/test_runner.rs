@@ -1,58 +1,544 @@
-use core::num;
+use std::time::SystemTime;
 
 use tokio::time::Instant;
 
-use crate::{load_test::LoadTest, test_result::TestResult};
+use crate::error::Error;
+use crate::fault::ErrorPolicy;
+use crate::filesystem::FilesystemInfo;
+use crate::load_test::LoadTest;
+use crate::page_cache::{self, PageCacheReport};
+use crate::queue::{Capacity, QueueReport};
+use crate::run_config::{Durability, RunConfig, Workload};
+use crate::storage::{Backing, GrowthPolicy};
+use crate::test_result::{PhaseResult, TestResult, WindowResult};
+use crate::workload;
 
+// One run of one scenario, what the structured output is written from
 pub struct TestRunResult {
-    pub num_writes: usize,
+    pub test: String,
+    pub workload: String,
+    pub growth: GrowthPolicy,
+    pub capacity: Capacity,
+    // Counting from 1
+    pub repetition: usize,
+    pub started_at: SystemTime,
+    // The whole run, setup to analysis
     pub run_duration_ns: f64,
-    pub result: Vec<TestResult>,
+    pub result: TestResult,
+}
+
+impl TestRunResult {
+    pub fn writes_per_s(&self) -> f64 {
+        self.result.writes_per_s()
+    }
+}
+
+// A scenario that errored or whose file didn't verify
+pub struct Failure {
+    pub test: String,
+    pub workload: String,
+    pub error: Error,
 }
 
 pub struct TestRunner {
+    pub config: RunConfig,
     pub test_runs: Vec<TestRunResult>,
+    pub failures: Vec<Failure>,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
 }
 
 impl TestRunner {
-    pub fn new() -> Self {
+    pub fn new(config: RunConfig) -> Self {
         Self {
+            config,
             test_runs: Vec::new(),
+            failures: Vec::new(),
+            started_at: SystemTime::now(),
+            finished_at: SystemTime::now(),
         }
     }
 
-    pub async fn run_tests(&self, tests: Vec<Box<dyn LoadTest>>) {
+    // Runs every scenario even if some fail, keeping every result in `test_runs` and every failure in
+    // `failures`, and returns the results
+    pub async fn run_tests(&mut self, tests: Vec<Box<dyn LoadTest>>) -> &[TestRunResult] {
+        let mut failures = Vec::new();
+        let mut scenarios = 0;
+        self.started_at = SystemTime::now();
+        println!("Seed {}", self.config.seed);
+
+        // Preflight: what the log files are going to be written to
+        let filesystem = match FilesystemInfo::detect(&self.config.target_dir) {
+            Ok(filesystem) => Some(filesystem),
+            Err(error) => {
+                println!("Failed to detect the filesystem of {}: {}", self.config.target_dir.display(), error);
+                None
+            }
+        };
+        if let Some(device) = &self.config.device {
+            let backing = match self.config.device_backing {
+                Backing::Memory => "memory",
+                Backing::File => "the log file",
+            };
+            println!("Simulating storage device {}, backed by {}", device, backing);
+            println!();
+        }
         for test in tests {
             let start = Instant::now();
+            let mut sweep = Vec::new();
+
+            for workload in &self.config.workloads {
+                let mut growth_sweep = Vec::new();
+                for (index, growth) in self.config.growth.iter().enumerate() {
+                    let growth = growth.resolve(workload::expected_bytes(workload, &self.config));
+                    // Strategies without a channel have nothing to size
+                    let capacities = match test.channel() {
+                        true => self.config.capacities.clone(),
+                        false => vec![Capacity::Default],
+                    };
+                    let mut capacity_sweep = Vec::new();
+                    for (capacity_index, &capacity) in capacities.iter().enumerate() {
+                        for repetition in 1..=self.config.repetitions {
+                            let mut description = self.describe(workload, &growth, capacity);
+                            if self.config.repetitions > 1 {
+                                description = format!("{}, run {} of {}", description, repetition, self.config.repetitions);
+                            }
+                            println!("===== Running test {} ({}) =====", test.get_name(), description);
+                            scenarios += 1;
+                            let started_at = SystemTime::now();
+                            let run_start = Instant::now();
+                            let result = match workload::run(test.as_ref(), workload, &growth, capacity, &self.config).await {
+                                Ok(result) => result,
+                                Err(error) => {
+                                    println!("  Failed:       {}", error);
+                                    println!();
+                                    failures.push(Self::failure(test.as_ref(), &description, error));
+                                    continue;
+                                }
+                            };
+                            let run_duration_ns = Instant::now().duration_since(run_start).as_nanos() as f64;
+                            self.print_results(&result, run_duration_ns, test.error_policy(), filesystem.as_ref());
+                            if !result.is_valid() {
+                                let error = Error::Verification(Self::problems(&result));
+                                failures.push(Self::failure(test.as_ref(), &description, error));
+                            }
 
-            let run_start = Instant::now();
-            // let num_writes = 1_000_000;
-            let num_writes = 100_000;
-            let size_range = 300..2_000;
-            let result = test.run(num_writes, size_range.clone()).await;
-            println!("===== Running test {} ({} writes of size {:?}) =====", test.get_name(), num_writes, size_range);
-            let run_duration_ns = Instant::now().duration_since(run_start).as_nanos() as f64;
-            Self::print_results(result, num_writes, run_duration_ns);
+                            let run = TestRunResult {
+                                test: test.get_name().to_string(),
+                                workload: self.describe_workload(workload),
+                                growth: growth.clone(),
+                                capacity,
+                                repetition,
+                                started_at,
+                                run_duration_ns,
+                                result,
+                            };
+                            // The sweeps only compare the first run of each scenario, the concurrency sweep only under
+                            // the first growth policy and capacity, and the growth sweep only under the first capacity
+                            if repetition == 1 {
+                                if let (Workload::ClosedLoop { clients }, 0, 0) = (workload, index, capacity_index) {
+                                    sweep.push((*clients, run.writes_per_s()));
+                                }
+                                let result = &run.result;
+                                if capacity_index == 0 {
+                                    growth_sweep.push((growth.clone(), result.median, result.p99, result.max));
+                                }
+                                capacity_sweep.push(self.test_runs.len());
+                            }
+                            self.test_runs.push(run);
+                        }
+                    }
+
+                    if capacity_sweep.len() > 1 {
+                        let runs: Vec<&TestRunResult> = capacity_sweep.iter().map(|&index| &self.test_runs[index]).collect();
+                        Self::print_capacity(&runs);
+                    }
+                }
+
+                if growth_sweep.len() > 1 {
+                    Self::print_growth(&growth_sweep);
+                }
+            }
+
+            // Throughput vs concurrency, the knee is where adding clients stops adding writes/s
+            if sweep.len() > 1 {
+                println!("  Clients    Writes/s");
+                for (clients, writes_per_s) in sweep {
+                    println!("  {:<10} {:.2}", clients, writes_per_s);
+                }
+                println!();
+            }
 
             let total_duration = Instant::now().duration_since(start).as_nanos() as f64;
             let total_duration_ms = total_duration / 1_000_000.0;
             let total_duration_s = total_duration_ms / 1000.0;
 
             println!("Completed test for {} in {:.2} s ({:.2} ms)", test.get_name(), total_duration_s, total_duration_ms);
-            println!("");
+            println!();
+        }
+
+        self.finished_at = SystemTime::now();
+        if failures.is_empty() {
+            println!("All {} scenarios passed", scenarios);
+        } else {
+            println!("{} of {} scenarios failed:", failures.len(), scenarios);
+            for failure in &failures {
+                println!("  {} ({}): {}", failure.test, failure.workload, failure.error);
+            }
         }
+        self.failures = failures;
+        &self.test_runs
+    }
+
+    fn failure(test: &dyn LoadTest, description: &str, error: Error) -> Failure {
+        Failure { test: test.get_name().to_string(), workload: description.to_string(), error }
     }
 
-    fn print_results(result: TestResult, num_writes: usize, run_duration_ns: f64) {
+    // Signs the run never really touched a disk, a simulated device doesn't care where its file goes
+    fn warnings(&self, result: &TestResult, filesystem: Option<&FilesystemInfo>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.config.device.is_some() {
+            return warnings;
+        }
+        if let Some(filesystem) = filesystem.filter(|filesystem| filesystem.is_memory_backed()) {
+            warnings.push(format!("the target is on {}, nothing was written to a disk", filesystem.fs_type));
+        }
+        let dirty_limit = page_cache::dirty_limit().unwrap_or(0);
+        if self.config.durability == Durability::Written && result.total_bytes < dirty_limit {
+            warnings.push(format!(
+                "wrote {:.2} MB, under the {:.2} MB the page cache holds before writers wait on writeback",
+                result.total_bytes as f64 / 1_000_000.0,
+                dirty_limit as f64 / 1_000_000.0
+            ));
+        }
+        warnings
+    }
+
+    // What made a result invalid
+    fn problems(result: &TestResult) -> String {
+        let verification = &result.verification;
+        let mut problems = Vec::new();
+        for (count, problem) in [
+            (verification.missing, "missing"),
+            (verification.duplicated, "duplicated"),
+            (verification.torn, "torn"),
+            (verification.corrupted, "corrupted"),
+        ] {
+            if count > 0 {
+                problems.push(format!("{} {}", count, problem));
+            }
+        }
+        if result.ordering.is_broken() {
+            problems.push(format!("{} ordering broken", result.ordering.guarantee));
+        }
+        problems.join(", ")
+    }
+
+    fn describe(&self, workload: &Workload, growth: &GrowthPolicy, capacity: Capacity) -> String {
+        let mut description = match growth {
+            GrowthPolicy::None => self.describe_workload(workload),
+            growth => format!("{}, growth {}", self.describe_workload(workload), growth),
+        };
+        if capacity != Capacity::Default {
+            description = format!("{}, capacity {}", description, capacity);
+        }
+        description
+    }
+
+    pub fn describe_workload(&self, workload: &Workload) -> String {
+        let writes = match self.config.duration {
+            Some(duration) => match workload {
+                Workload::OpenLoop => {
+                    format!("up to {} writes in flight for {:.2} s", self.config.num_writes, duration.as_secs_f64())
+                }
+                _ => format!("writes for {:.2} s", duration.as_secs_f64()),
+            },
+            None => format!("{} writes", self.config.num_writes),
+        };
+        match workload {
+            Workload::OpenLoop => format!("{} of size {:?}", writes, self.config.size_range),
+            Workload::ClosedLoop { clients } => {
+                format!("{} clients, {} of size {:?}", clients, writes, self.config.size_range)
+            }
+            Workload::Profile(phases) => format!("{} phases of size {:?}", phases.len(), self.config.size_range),
+            Workload::Trace { trace, speed } => format!(
+                "trace of {} writes from {} producers over {:.2} s at {}x",
+                trace.records.len(),
+                trace.producers(),
+                trace.duration().as_secs_f64(),
+                speed
+            ),
+        }
+    }
+
+    fn print_results(
+        &self,
+        result: &TestResult,
+        run_duration_ns: f64,
+        error_policy: ErrorPolicy,
+        filesystem: Option<&FilesystemInfo>,
+    ) {
         let run_duration_ms = run_duration_ns / 1_000_000.0;
-        let writes_per_s = num_writes as f64 / (run_duration_ms / 1000.0);
+        let timings = &result.timings;
+        let ms = |ns: f64| ns / 1_000_000.0;
         println!("  Mean:         {:.2} ms ({:.2} ns)", result.mean / 1_000_000.0, result.mean);
         println!("  Median:       {:.2} ms ({:.2} ns)", result.median / 1_000_000.0, result.median);
         println!("  P90:          {:.2} ms ({:.2} ns)", result.p90 / 1_000_000.0, result.p90);
-        println!("  Writes/s:     {:.2}", writes_per_s);
+        println!("  P99:          {:.2} ms ({:.2} ns)", result.p99 / 1_000_000.0, result.p99);
+        println!("  Max:          {:.2} ms ({:.2} ns)", result.max / 1_000_000.0, result.max);
+        let caller = &result.caller;
+        println!(
+            "  Caller:       mean {:.3} ms, P50 {:.3} ms, P90 {:.3} ms, P99 {:.3} ms, max {:.3} ms until write returned",
+            ms(caller.mean),
+            ms(caller.median),
+            ms(caller.p90),
+            ms(caller.p99),
+            ms(caller.max)
+        );
+        println!("  Writes/s:     {:.2}", result.writes_per_s());
+        println!("  MB/s:         {:.2}", result.mb_per_s());
         println!("  Total Bytes:  {:.2} MB", result.total_bytes as f64 / 1_000_000.0);
         println!("  Total Writes: {:.2}", result.num_writes);
         println!("  Total Time:   {:.2} ms", run_duration_ms);
-        println!("");
+        println!(
+            "  Timing:       setup {:.2} ms, write {:.2} ms, drain {:.2} ms, teardown {:.2} ms, analysis {:.2} ms",
+            ms(timings.setup_ns),
+            ms(timings.write_ns),
+            ms(timings.drain_ns),
+            ms(timings.teardown_ns),
+            ms(timings.analysis_ns)
+        );
+        println!("  Target:       {}", self.config.target_dir.display());
+        match filesystem {
+            Some(filesystem) => println!("  Filesystem:   {}", filesystem),
+            None => println!("  Filesystem:   unknown"),
+        }
+        for warning in self.warnings(result, filesystem) {
+            println!("  Warning:      {}", warning);
+        }
+        if self.config.device.is_none() {
+            Self::print_page_cache(&result.page_cache);
+        }
+        Self::print_resources(result);
+        if let Some(queue) = &result.queue {
+            Self::print_queue(queue);
+        }
+        if let Some(allocations) = &result.allocations {
+            println!(
+                "  Allocations:  {} ({:.2} per write), {:.2} MB allocated ({:.0} bytes per write), peak {:.2} MB live",
+                allocations.allocations,
+                allocations.per_write(result.num_writes),
+                allocations.allocated_bytes as f64 / 1_000_000.0,
+                allocations.bytes_per_write(result.num_writes),
+                allocations.peak_live_bytes as f64 / 1_000_000.0
+            );
+        }
+        let verification = &result.verification;
+        if verification.is_valid() {
+            let mut notes = Vec::new();
+            if verification.unacked > 0 {
+                notes.push(format!("{} of them from failed writes", verification.unacked));
+            }
+            if verification.abandoned > 0 {
+                notes.push(format!("{} stretches left by failed writes", verification.abandoned));
+            }
+            match notes.is_empty() {
+                true => println!("  Verified:     {} records", verification.records),
+                false => println!("  Verified:     {} records, {}", verification.records, notes.join(", ")),
+            }
+        } else {
+            println!(
+                "  Verified:     INVALID {} records, {} missing, {} duplicated, {} torn, {} corrupted",
+                verification.records, verification.missing, verification.duplicated, verification.torn, verification.corrupted
+            );
+        }
+        let ordering = &result.ordering;
+        println!(
+            "  Ordering:     {}{} promised, {} out of order overall, {} within a producer",
+            if ordering.is_broken() { "BROKEN " } else { "" },
+            ordering.guarantee,
+            ordering.total_violations,
+            ordering.producer_violations
+        );
+        let faults = &result.faults;
+        if !faults.is_empty() || result.failed > 0 {
+            println!(
+                "  Faults:       {} short writes, {} EINTR, {} ENOSPC, {} EIO",
+                faults.short_writes, faults.interrupted, faults.no_space, faults.io_errors
+            );
+            println!(
+                "  Errors:       {} policy, {} retried, {} failed the request, {} aborted the run",
+                error_policy, faults.retried, faults.failed, faults.aborted
+            );
+            println!("  Failed:       {} writes", result.failed);
+        }
+        if result.is_aborted() {
+            println!("  Result:       ABORTED");
+        }
+        if !result.is_valid() {
+            println!("  Result:       INVALID");
+        }
+        println!();
+
+        if !result.phases.is_empty() {
+            Self::print_phases(&result.phases);
+        }
+        if !result.timeline.is_empty() {
+            Self::print_timeline(&result.timeline);
+        }
+    }
+
+    // System wide figures, anything else writing at the same time shows up in them too
+    fn print_page_cache(page_cache: &PageCacheReport) {
+        let megabytes = |bytes: u64| bytes as f64 / 1_000_000.0;
+        println!(
+            "  Page cache:   {:.2} MB dirty before, peak {:.2} MB dirty and {:.2} MB under writeback",
+            megabytes(page_cache.before.dirty),
+            megabytes(page_cache.peak_dirty),
+            megabytes(page_cache.peak_writeback)
+        );
+        println!(
+            "  Left dirty:   {:.2} MB not on disk when the strategy finished, {:.2} MB still {:.0} ms later",
+            megabytes(page_cache.left_dirty()),
+            megabytes(page_cache.after.outstanding().saturating_sub(page_cache.before.outstanding())),
+            page_cache.after_finished_ns / 1_000_000.0
+        );
+        println!(
+            "  Writeback:    {:.2} MB dirtied and {:.2} MB written back while the strategy ran",
+            megabytes(page_cache.dirtied()),
+            megabytes(page_cache.written_back())
+        );
+        if let Some(final_sync_ns) = page_cache.final_sync_ns {
+            println!("  Final sync:   {:.2} ms", final_sync_ns / 1_000_000.0);
+        }
+    }
+
+    fn print_resources(result: &TestResult) {
+        let resources = &result.resources;
+        println!(
+            "  CPU:          {:.2} ms user, {:.2} ms system, {:.2} us per write",
+            resources.user.as_secs_f64() * 1000.0,
+            resources.system.as_secs_f64() * 1000.0,
+            resources.cpu_us_per_write(result.num_writes)
+        );
+        println!(
+            "  Switches:     {} voluntary, {} involuntary",
+            resources.voluntary_switches, resources.involuntary_switches
+        );
+        println!(
+            "  Syscalls:     {} writes of {:.0} bytes on average, {} reads of {:.2} MB in all",
+            resources.write_syscalls,
+            resources.bytes_per_write_syscall(),
+            resources.read_syscalls,
+            resources.bytes_read as f64 / 1_000_000.0
+        );
+        println!("  Peak RSS:     {:.2} MB", resources.peak_rss as f64 / 1_000_000.0);
+    }
+
+    fn print_timeline(timeline: &[WindowResult]) {
+        // Strategies with a queue get a column for how deep it was sampled at
+        let queued = timeline.iter().any(|window| window.queue_depth.is_some());
+        println!(
+            "  {:>8} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10}{}",
+            "At s",
+            "Writes",
+            "Writes/s",
+            "MB/s",
+            "P50 ms",
+            "P90 ms",
+            "P99 ms",
+            if queued { format!(" {:>10}", "Queued") } else { String::new() }
+        );
+        for window in timeline {
+            let window_s = window.duration_ns / 1_000_000_000.0;
+            let depth = match (queued, window.queue_depth) {
+                (false, _) => String::new(),
+                (true, Some(depth)) => format!(" {:>10}", depth),
+                (true, None) => format!(" {:>10}", "-"),
+            };
+            println!(
+                "  {:>8.2} {:>10} {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}{}",
+                window.start_ns / 1_000_000_000.0,
+                window.result.num_writes,
+                window.result.num_writes as f64 / window_s,
+                window.result.total_bytes as f64 / 1_000_000.0 / window_s,
+                window.result.median / 1_000_000.0,
+                window.result.p90 / 1_000_000.0,
+                window.result.p99 / 1_000_000.0,
+                depth
+            );
+        }
+        println!();
+    }
+
+    fn print_queue(queue: &QueueReport) {
+        let capacity = queue.capacity.map_or_else(|| "unbounded".to_string(), |capacity| capacity.to_string());
+        println!(
+            "  Queue:        max {} of {} queued, peak {:.2} MB of payloads",
+            queue.max_depth,
+            capacity,
+            queue.peak_bytes as f64 / 1_000_000.0
+        );
+        println!(
+            "  Send blocked: {:.2} ms summed over senders, {:.3} us per send",
+            queue.blocked_ns / 1_000_000.0,
+            queue.blocked_ns_per_send() / 1000.0
+        );
+    }
+
+    // Throughput, latency and queue growth under each channel capacity
+    fn print_capacity(runs: &[&TestRunResult]) {
+        println!(
+            "  {:<12} {:>12} {:>10} {:>14} {:>10} {:>10} {:>12}",
+            "Capacity", "Writes/s", "P99 ms", "Caller P99 ms", "Max queue", "Queue MB", "Blocked ms"
+        );
+        for run in runs {
+            let queue = run.result.queue.clone().unwrap_or_default();
+            println!(
+                "  {:<12} {:>12.2} {:>10.2} {:>14.3} {:>10} {:>10.2} {:>12.2}",
+                run.capacity.to_string(),
+                run.writes_per_s(),
+                run.result.p99 / 1_000_000.0,
+                run.result.caller.p99 / 1_000_000.0,
+                queue.max_depth,
+                queue.peak_bytes as f64 / 1_000_000.0,
+                queue.blocked_ns / 1_000_000.0
+            );
+        }
+        println!();
+    }
+
+    // Tail latency under each growth policy, against the first one listed
+    fn print_growth(sweep: &[(GrowthPolicy, f64, f64, f64)]) {
+        println!("  {:<24} {:>10} {:>10} {:>10} {:>12}", "Growth", "P50 ms", "P99 ms", "Max ms", "P99 change");
+        let baseline = sweep[0].2;
+        for (growth, median, p99, max) in sweep {
+            println!(
+                "  {:<24} {:>10.2} {:>10.2} {:>10.2} {:>11.1}%",
+                growth.to_string(),
+                median / 1_000_000.0,
+                p99 / 1_000_000.0,
+                max / 1_000_000.0,
+                (p99 / baseline - 1.0) * 100.0,
+            );
+        }
+        println!();
+    }
+
+    fn print_phases(phases: &[PhaseResult]) {
+        println!("  {:<32} {:>10} {:>10} {:>10} {:>12} {:>12}", "Phase", "Offered/s", "Acked/s", "Writes", "Mean ms", "P90 ms");
+        for phase in phases {
+            let acked_per_s = phase.acked as f64 / (phase.duration_ns / 1_000_000_000.0);
+            println!(
+                "  {:<32} {:>10.0} {:>10.0} {:>10} {:>12.2} {:>12.2}",
+                phase.name,
+                phase.offered_rate,
+                acked_per_s,
+                phase.result.num_writes,
+                phase.result.mean / 1_000_000.0,
+                phase.result.p90 / 1_000_000.0,
+            );
+        }
+        println!();
     }
 }
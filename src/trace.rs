@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::{Error, Result};
+use crate::profile::{self, Phase};
+use crate::run_config::{parse, parse_range};
+
+// One write from a recorded trace, see the trace format section of the README
+#[derive(Clone, Debug)]
+pub struct TraceRecord {
+    pub offset: Duration,
+    pub size: usize,
+    pub producer: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Trace {
+    pub records: Vec<TraceRecord>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(Error::setup("opening the trace"))?;
+        Self::parse(BufReader::new(file))
+    }
+
+    fn parse(reader: impl BufRead) -> Result<Self> {
+        let mut records = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(Error::setup("reading the trace"))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || Error::invalid("loading the trace", format!("Invalid record on line {}: {}", number + 1, line));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 || fields.len() > 3 {
+                return Err(invalid());
+            }
+            let record = TraceRecord {
+                offset: Duration::from_micros(fields[0].parse().map_err(|_| invalid())?),
+                size: fields[1].parse().map_err(|_| invalid())?,
+                producer: fields.get(2).map(|producer| producer.parse().map_err(|_| invalid())).transpose()?,
+            };
+            if records.last().is_some_and(|last: &TraceRecord| last.offset > record.offset) {
+                let message = format!("Offsets must not go backwards on line {}", number + 1);
+                return Err(Error::invalid("loading the trace", message));
+            }
+            records.push(record);
+        }
+        Ok(Trace { records })
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.records.last().map(|record| record.offset).unwrap_or_default()
+    }
+
+    pub fn producers(&self) -> usize {
+        let mut producers: Vec<u32> = self.records.iter().filter_map(|record| record.producer).collect();
+        producers.sort_unstable();
+        producers.dedup();
+        producers.len()
+    }
+}
+
+// gen-trace: writes a synthetic trace shaped by a workload profile
+pub fn generate(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut phases = profile::production(10_000.0);
+    let mut size_range = 300..2_000;
+    let mut producers: u32 = 0;
+    let mut out: Option<String> = None;
+    let mut seed: u64 = rand::random();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| Error::argument(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--phases" => phases = value()?.split(',').map(Phase::parse).collect::<Result<_>>()?,
+            "--profile" => phases = profile::production(parse(&value()?)?),
+            "--size" => size_range = parse_range(&value()?)?,
+            "--producers" => producers = parse(&value()?)?,
+            "--out" => out = Some(value()?),
+            "--seed" => seed = parse(&value()?)?,
+            _ => return Err(Error::argument(format!("Unknown argument {}", arg))),
+        }
+    }
+
+    let writer: Box<dyn Write> = match out {
+        Some(path) => Box::new(File::create(path).map_err(Error::setup("creating the trace"))?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = BufWriter::new(writer);
+    let header = format!("# offset_us size_bytes{}", if producers > 0 { " producer" } else { "" });
+    writeln!(writer, "{}", header).map_err(Error::io("writing the trace"))?;
+
+    // The trace itself may be going to stdout
+    eprintln!("Seed {}", seed);
+
+    // Spread each millisecond's share of the profile randomly across that millisecond
+    let mut rng = StdRng::seed_from_u64(seed);
+    let slot = Duration::from_millis(1);
+    let mut phase_start = Duration::ZERO;
+    for phase in &phases {
+        let mut elapsed = Duration::ZERO;
+        while elapsed < phase.duration {
+            let count = phase.expected_writes(elapsed + slot) - phase.expected_writes(elapsed);
+            let mut offsets: Vec<u64> = (0..count).map(|_| rng.gen_range(0..slot.as_micros() as u64)).collect();
+            offsets.sort_unstable();
+            for offset in offsets {
+                let offset_us = (phase_start + elapsed).as_micros() as u64 + offset;
+                let size = rng.gen_range(size_range.clone());
+                let line = if producers > 0 {
+                    format!("{} {} {}", offset_us, size, rng.gen_range(0..producers))
+                } else {
+                    format!("{} {}", offset_us, size)
+                };
+                writeln!(writer, "{}", line).map_err(Error::io("writing the trace"))?;
+            }
+            elapsed += slot;
+        }
+        phase_start += phase.duration;
+    }
+    writer.flush().map_err(Error::io("writing the trace"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Trace {
+        Trace::parse(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_records() {
+        let trace = parse("# offset_us size_bytes [producer]\n0 512\n\n250 1830 3\n  250 0 1  \n");
+        let records: Vec<(u64, usize, Option<u32>)> = trace
+            .records
+            .iter()
+            .map(|record| (record.offset.as_micros() as u64, record.size, record.producer))
+            .collect();
+        assert_eq!(records, vec![(0, 512, None), (250, 1830, Some(3)), (250, 0, Some(1))]);
+        assert_eq!(trace.duration(), Duration::from_micros(250));
+        assert_eq!(trace.producers(), 2);
+    }
+
+    #[test]
+    fn parses_an_empty_trace() {
+        let trace = parse("# nothing here\n");
+        assert!(trace.records.is_empty());
+        assert_eq!(trace.duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for text in ["0", "0 512 1 2", "x 512", "0 -1", "0 512 first", "0 1.5"] {
+            assert!(Trace::parse(text.as_bytes()).is_err(), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_going_backwards() {
+        let error = Trace::parse("100 512\n50 512\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("must not go backwards on line 2"), "{}", error);
+    }
+}
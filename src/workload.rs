@@ -0,0 +1,595 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::Builder;
+use tokio::sync::Semaphore;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+
+use crate::alloc::AllocationCounter;
+use crate::error::{Error, Result};
+use crate::load_test::{LoadTest, LogWriter, WriteError};
+use crate::ordering::{self, Submission};
+use crate::page_cache::Monitor;
+use crate::profile::Phase;
+use crate::queue::{Capacity, QueueMonitor};
+use crate::record::{self, Record, HEADER_LEN};
+use crate::resources::Accounting;
+use crate::run_config::{RunConfig, Workload};
+use crate::storage::{GrowthPolicy, Storage};
+use crate::test_result::{LatencySummary, PhaseResult, RunTimings, TestResult, WindowResult};
+use crate::trace::Trace;
+
+// A finished write, with times relative to the start of the run
+struct Sample {
+    sequence: u64,
+    phase: usize,
+    submitted_ns: f64,
+    // When `write` returned and the producer was free to move on
+    returned_ns: f64,
+    latency_ns: f64,
+    bytes: u64,
+    // The strategy gave up on the write instead of acking it
+    failed: bool,
+}
+
+impl Sample {
+    fn acked_ns(&self) -> f64 {
+        self.submitted_ns + self.latency_ns
+    }
+}
+
+// Hands out records numbered in submission order so the file can be verified after the run
+#[derive(Default)]
+pub struct MessageSource {
+    next_sequence: AtomicU64,
+}
+
+impl MessageSource {
+    pub fn next(&self, producer: u32, size: usize) -> Record {
+        Record::generated(self.next_sequence.fetch_add(1, Ordering::SeqCst), producer, size)
+    }
+
+    pub fn random(&self, producer: u32, rng: &mut StdRng, size_range: &Range<usize>) -> Record {
+        self.next(producer, rng.gen_range(size_range.clone()))
+    }
+
+    fn issued(&self) -> u64 {
+        self.next_sequence.load(Ordering::SeqCst)
+    }
+}
+
+// Tells producers to stop issuing new writes, anything already in flight still gets acked
+#[derive(Default)]
+pub struct StopSignal {
+    stopped: AtomicBool,
+}
+
+impl StopSignal {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+// Roughly how many bytes a workload will write, for preallocating its file. A duration based run can't say.
+pub fn expected_bytes(workload: &Workload, config: &RunConfig) -> Option<u64> {
+    let mean_len = ((config.size_range.start + config.size_range.end) / 2 + HEADER_LEN) as u64;
+    let writes = match workload {
+        Workload::OpenLoop | Workload::ClosedLoop { .. } if config.duration.is_some() => return None,
+        Workload::OpenLoop | Workload::ClosedLoop { .. } => config.num_writes as u64,
+        Workload::Profile(phases) => phases.iter().map(|phase| phase.expected_writes(phase.duration)).sum(),
+        Workload::Trace { trace, .. } => {
+            return Some(trace.records.iter().map(|record| (record.size + HEADER_LEN) as u64).sum());
+        }
+    };
+    Some(writes * mean_len)
+}
+
+pub async fn run(
+    test: &dyn LoadTest,
+    workload: &Workload,
+    growth: &GrowthPolicy,
+    capacity: Capacity,
+    config: &RunConfig,
+) -> Result<TestResult> {
+    let setup_start = Instant::now();
+    // Create a temporary directory for the test
+    let dir = Builder::new()
+        .prefix("rusty-writes-")
+        .tempdir_in(&config.target_dir)
+        .map_err(Error::setup("creating the temp directory"))?;
+    let file_path = dir.path().join("test_log");
+    let storage = Storage::new(config, growth);
+    let writer = Arc::new(test.open(&file_path, config, &storage, capacity).await?);
+
+    // Duration based runs are stopped by a timer instead of running out of writes
+    let stop = Arc::new(StopSignal::default());
+    if let Some(duration) = config.duration {
+        let stop = Arc::clone(&stop);
+        tokio::spawn(async move {
+            sleep(duration).await;
+            stop.stop();
+        });
+    }
+
+    let messages = Arc::new(MessageSource::default());
+    let page_cache = Monitor::start(config.sample_interval);
+    let accounting = Accounting::start();
+    let allocations = AllocationCounter::start();
+    let start = Instant::now();
+    let queue = writer.queue().map(|gauge| QueueMonitor::start(gauge, config.sample_interval, start.into()));
+    let samples = match workload {
+        Workload::OpenLoop => open_loop(test, &writer, &messages, config, &stop, start).await,
+        Workload::ClosedLoop { clients } => closed_loop(&writer, &messages, *clients, config, &stop, start).await,
+        Workload::Profile(phases) => profile(test, &writer, &messages, phases, config, &stop, start).await,
+        Workload::Trace { trace, speed } => replay(test, &writer, &messages, trace, *speed, &stop, start).await,
+    };
+    let write_end = Instant::now();
+
+    // Every producer has finished, so this is the last reference to the writer. It's closed even if a
+    // producer failed, unless that left other producers still running, and the producer's error wins.
+    let closed = match Arc::try_unwrap(writer) {
+        Ok(writer) => writer.close().await,
+        Err(_) => Err(Error::Task("writer still in use after the run".to_string())),
+    };
+    let samples = samples?;
+    closed?;
+
+    // Buffered strategies can return with much of their data still dirty, syncing makes the run wait for it.
+    // A simulated device already charged for its syncs.
+    let final_sync_ns = match config.final_sync && config.device.is_none() {
+        true => {
+            let path = file_path.clone();
+            let sync_start = Instant::now();
+            tokio::task::spawn_blocking(move || std::fs::File::open(path)?.sync_data())
+                .await
+                .map_err(Error::task)?
+                .map_err(Error::io("syncing the log file"))?;
+            Some(sync_start.elapsed().as_nanos() as f64)
+        }
+        false => None,
+    };
+    let drain_end = Instant::now();
+    let queue = queue.map(QueueMonitor::finish);
+    let resources = accounting.finish();
+    let allocations = allocations.map(AllocationCounter::finish);
+    let page_cache = page_cache.finish(final_sync_ns);
+    storage.finish(&file_path).map_err(Error::io("writing out the simulated device"))?;
+
+    // Check every acked record made it into the file exactly once and intact, and in the promised order
+    let expected = messages.issued() as usize;
+    let mut submissions = vec![Submission::default(); expected];
+    let mut acked = vec![false; expected];
+    for sample in &samples {
+        submissions[sample.sequence as usize] = Submission { start_ns: sample.submitted_ns, returned_ns: sample.returned_ns };
+        acked[sample.sequence as usize] = !sample.failed;
+    }
+    let guarantee = test.ordering();
+    let abandoned = storage.abandoned();
+    let (verification, ordering) = tokio::task::spawn_blocking(move || {
+        (record::verify(&file_path, &acked, &abandoned), ordering::check(&file_path, guarantee, &submissions))
+    })
+    .await
+    .map_err(|e| Error::Verification(e.to_string()))?;
+    let verification = verification.map_err(|e| Error::Verification(e.to_string()))?;
+    let ordering = ordering.map_err(|e| Error::Verification(e.to_string()))?;
+    let page_cache = page_cache.after();
+
+    dir.close().map_err(Error::io("deleting the temp directory"))?;
+    let teardown_end = Instant::now();
+
+    // Failed writes only show up in the error counts, every other figure is for acked writes
+    let (failed, samples): (Vec<Sample>, Vec<Sample>) = samples.into_iter().partition(|s| s.failed);
+    let mut result = summarize(&samples, |_| true);
+    result.caller = LatencySummary::from_latencies(samples.iter().map(|s| s.returned_ns - s.submitted_ns).collect());
+    result.failed = failed.len() as u64;
+    result.faults = storage.fault_report();
+    result.verification = verification;
+    result.ordering = ordering;
+    result.page_cache = page_cache;
+    result.resources = resources;
+    if let Workload::Profile(phases) = workload {
+        result.phases = phase_results(&samples, phases);
+    }
+    result.timeline = timeline(&samples, config.sample_interval, config.duration);
+    if let Some(queue) = &queue {
+        // Deepest the queue was sampled at in each window
+        for window in &mut result.timeline {
+            let end_ns = window.start_ns + window.duration_ns;
+            let sampled = queue.samples.iter().filter(|sample| (window.start_ns..end_ns).contains(&sample.at_ns));
+            window.queue_depth = sampled.map(|sample| sample.depth).max();
+        }
+    }
+    result.queue = queue;
+    result.allocations = allocations;
+    result.timings = RunTimings {
+        setup_ns: start.duration_since(setup_start).as_nanos() as f64,
+        write_ns: write_end.duration_since(start).as_nanos() as f64,
+        drain_ns: drain_end.duration_since(write_end).as_nanos() as f64,
+        teardown_ns: teardown_end.duration_since(drain_end).as_nanos() as f64,
+        analysis_ns: teardown_end.elapsed().as_nanos() as f64,
+    };
+    Ok(result)
+}
+
+async fn open_loop(
+    test: &dyn LoadTest,
+    writer: &Arc<Box<dyn LogWriter>>,
+    messages: &MessageSource,
+    config: &RunConfig,
+    stop: &Arc<StopSignal>,
+    start: Instant,
+) -> Result<Vec<Sample>> {
+    let mut samples = Vec::with_capacity(config.num_writes);
+    let mut tasks = Vec::new();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    // Duration based runs keep at most `num_writes` in flight, otherwise this never blocks
+    let in_flight = Arc::new(Semaphore::new(config.num_writes.max(1)));
+    let mut issued = 0;
+    loop {
+        let done = match config.duration {
+            Some(_) => stop.is_stopped(),
+            None => issued >= config.num_writes || stop.is_stopped(),
+        };
+        if done {
+            break;
+        }
+        issued += 1;
+
+        // Generate a random message
+        let record = messages.random(0, &mut rng, &config.size_range);
+
+        if !test.concurrent() {
+            samples.push(write_and_ack(writer, record, 0, stop, start).await?);
+            continue;
+        }
+
+        // We create a new task for each write
+        let permit = Arc::clone(&in_flight).acquire_owned().await.map_err(|_| Error::ChannelClosed("in flight"))?;
+        let writer = Arc::clone(writer);
+        let stop = Arc::clone(stop);
+        tasks.push(tokio::spawn(async move {
+            let sample = write_and_ack(&writer, record, 0, &stop, start).await;
+            drop(permit);
+            sample
+        }));
+    }
+
+    // We then await all the tasks and collect their results
+    for task in tasks {
+        samples.push(task.await.map_err(Error::task)??);
+    }
+
+    Ok(samples)
+}
+
+async fn closed_loop(
+    writer: &Arc<Box<dyn LogWriter>>,
+    messages: &Arc<MessageSource>,
+    clients: usize,
+    config: &RunConfig,
+    stop: &Arc<StopSignal>,
+    start: Instant,
+) -> Result<Vec<Sample>> {
+    let next_write = Arc::new(AtomicUsize::new(0));
+    let timed = config.duration.is_some();
+
+    let mut tasks = Vec::new();
+    for client in 0..clients {
+        let writer = Arc::clone(writer);
+        let next_write = Arc::clone(&next_write);
+        let messages = Arc::clone(messages);
+        let stop = Arc::clone(stop);
+        let num_writes = config.num_writes;
+        let size_range = config.size_range.clone();
+        let seed = config.seed.wrapping_add(client as u64);
+        tasks.push(tokio::spawn(async move {
+            let mut samples = Vec::new();
+            let mut rng = StdRng::seed_from_u64(seed);
+            loop {
+                // Clients either share a fixed number of writes or keep going until stopped
+                let done = if timed {
+                    stop.is_stopped()
+                } else {
+                    stop.is_stopped() || next_write.fetch_add(1, Ordering::SeqCst) >= num_writes
+                };
+                if done {
+                    break;
+                }
+
+                let record = messages.random(client as u32, &mut rng, &size_range);
+                samples.push(write_and_ack(&writer, record, 0, &stop, start).await?);
+            }
+            Ok(samples)
+        }));
+    }
+
+    let mut all_samples = Vec::new();
+    for task in tasks {
+        let mut samples = task.await.map_err(Error::task)??;
+        all_samples.append(&mut samples);
+    }
+
+    Ok(all_samples)
+}
+
+async fn profile(
+    test: &dyn LoadTest,
+    writer: &Arc<Box<dyn LogWriter>>,
+    messages: &MessageSource,
+    phases: &[Phase],
+    config: &RunConfig,
+    stop: &Arc<StopSignal>,
+    start: Instant,
+) -> Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    let mut tasks = Vec::new();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let mut phase_start = start;
+    for (index, phase) in phases.iter().enumerate() {
+        let mut issued = 0;
+        let mut ticker = interval(Duration::from_millis(1));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            let elapsed = Instant::now().duration_since(phase_start);
+
+            // Catch up to the number of writes the phase should have issued by now
+            let target = phase.expected_writes(elapsed);
+            while issued < target {
+                let record = messages.random(0, &mut rng, &config.size_range);
+                if test.concurrent() {
+                    let writer = Arc::clone(writer);
+                    let stop = Arc::clone(stop);
+                    tasks.push(tokio::spawn(async move { write_and_ack(&writer, record, index, &stop, start).await }));
+                } else {
+                    samples.push(write_and_ack(writer, record, index, stop, start).await?);
+                }
+                issued += 1;
+            }
+
+            if elapsed >= phase.duration || stop.is_stopped() {
+                break;
+            }
+        }
+        // Phases are scheduled back to back so a slow phase doesn't shift the ones after it
+        phase_start += phase.duration;
+    }
+
+    for task in tasks {
+        samples.push(task.await.map_err(Error::task)??);
+    }
+
+    Ok(samples)
+}
+
+async fn replay(
+    test: &dyn LoadTest,
+    writer: &Arc<Box<dyn LogWriter>>,
+    messages: &MessageSource,
+    trace: &Trace,
+    speed: f64,
+    stop: &Arc<StopSignal>,
+    start: Instant,
+) -> Result<Vec<Sample>> {
+    let mut samples = Vec::with_capacity(trace.records.len());
+    let mut tasks = Vec::new();
+    let mut records = trace.records.iter().peekable();
+
+    let mut ticker = interval(Duration::from_millis(1));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    while records.peek().is_some() && !stop.is_stopped() {
+        ticker.tick().await;
+        let trace_time = Instant::now().duration_since(start).mul_f64(speed);
+
+        // Issue every record that is due by now in the trace's own time
+        while let Some(entry) = records.next_if(|entry| entry.offset <= trace_time) {
+            let record = messages.next(entry.producer.unwrap_or(0), entry.size);
+            if test.concurrent() {
+                let writer = Arc::clone(writer);
+                let stop = Arc::clone(stop);
+                tasks.push(tokio::spawn(async move { write_and_ack(&writer, record, 0, &stop, start).await }));
+            } else {
+                samples.push(write_and_ack(writer, record, 0, stop, start).await?);
+            }
+        }
+    }
+
+    for task in tasks {
+        samples.push(task.await.map_err(Error::task)??);
+    }
+
+    Ok(samples)
+}
+
+// Writes the record and waits for the ack, stopping the run if the strategy aborts it
+async fn write_and_ack(
+    writer: &Arc<Box<dyn LogWriter>>,
+    record: Record,
+    phase: usize,
+    stop: &StopSignal,
+    start: Instant,
+) -> Result<Sample> {
+    let bytes = record.encoded_len() as u64;
+    let sequence = record.sequence;
+    let submitted = Instant::now();
+    let ack = writer.write(record).await?;
+    let returned = Instant::now();
+    let outcome = ack.await.map_err(|_| Error::ChannelClosed("ack"))?;
+    let acked = Instant::now();
+    if outcome == Err(WriteError::Aborted) {
+        stop.stop();
+    }
+    Ok(Sample {
+        sequence,
+        phase,
+        submitted_ns: submitted.duration_since(start).as_nanos() as f64,
+        returned_ns: returned.duration_since(start).as_nanos() as f64,
+        latency_ns: acked.duration_since(submitted).as_nanos() as f64,
+        bytes,
+        failed: outcome.is_err(),
+    })
+}
+
+fn summarize(samples: &[Sample], filter: impl Fn(&Sample) -> bool) -> TestResult {
+    let latencies = samples.iter().filter(|s| filter(s)).map(|s| s.latency_ns).collect();
+    let total_bytes = samples.iter().filter(|s| filter(s)).map(|s| s.bytes).sum();
+    TestResult::from_latencies(latencies, total_bytes)
+}
+
+// Break the run down by phase, followed by the time it took to drain whatever was still queued
+fn phase_results(samples: &[Sample], phases: &[Phase]) -> Vec<PhaseResult> {
+    let mut phase_results = Vec::new();
+    let mut window_start = 0.0;
+    for (index, phase) in phases.iter().enumerate() {
+        let window_end = window_start + phase.duration.as_nanos() as f64;
+        phase_results.push(PhaseResult {
+            name: phase.to_string(),
+            duration_ns: window_end - window_start,
+            offered_rate: phase.offered_rate(),
+            acked: acked_between(samples, window_start, window_end),
+            result: summarize(samples, |s| s.phase == index),
+        });
+        window_start = window_end;
+    }
+    let last_ack = samples.iter().map(Sample::acked_ns).fold(window_start, f64::max);
+    phase_results.push(PhaseResult {
+        name: "drain".to_string(),
+        duration_ns: last_ack - window_start,
+        offered_rate: 0.0,
+        acked: acked_between(samples, window_start, f64::INFINITY),
+        result: TestResult::from_latencies(Vec::new(), 0),
+    });
+    phase_results
+}
+
+// Writes acked in each window of the run. Duration based runs get a final window for anything acked after the stop.
+fn timeline(samples: &[Sample], window: Duration, stop: Option<Duration>) -> Vec<WindowResult> {
+    let window_ns = window.as_nanos() as f64;
+    let last_ack = samples.iter().map(Sample::acked_ns).fold(0.0, f64::max);
+    let length_ns = stop.map_or(last_ack, |stop| stop.as_nanos() as f64);
+    let num_windows = (length_ns / window_ns).ceil().max(1.0) as usize;
+
+    // One bucket per window plus one for acks after the stop
+    let mut buckets: Vec<(Vec<f64>, u64)> = (0..=num_windows).map(|_| (Vec::new(), 0)).collect();
+    for sample in samples {
+        let index = if sample.acked_ns() >= length_ns && stop.is_some() {
+            num_windows
+        } else {
+            ((sample.acked_ns() / window_ns) as usize).min(num_windows - 1)
+        };
+        buckets[index].0.push(sample.latency_ns);
+        buckets[index].1 += sample.bytes;
+    }
+
+    let drain = buckets.pop().expect("Missing drain bucket");
+    let mut windows: Vec<WindowResult> = buckets
+        .into_iter()
+        .enumerate()
+        .map(|(index, (latencies, bytes))| {
+            let start_ns = index as f64 * window_ns;
+            WindowResult {
+                start_ns,
+                duration_ns: (start_ns + window_ns).min(length_ns.max(window_ns)) - start_ns,
+                result: TestResult::from_latencies(latencies, bytes),
+                queue_depth: None,
+            }
+        })
+        .collect();
+
+    if last_ack > length_ns && stop.is_some() {
+        windows.push(WindowResult {
+            start_ns: length_ns,
+            duration_ns: last_ack - length_ns,
+            result: TestResult::from_latencies(drain.0, drain.1),
+            queue_depth: None,
+        });
+    }
+    windows
+}
+
+fn acked_between(samples: &[Sample], start_ns: f64, end_ns: f64) -> u64 {
+    samples
+        .iter()
+        .filter(|s| (start_ns..end_ns).contains(&s.acked_ns()))
+        .count() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: f64 = 1_000_000.0;
+
+    // 100 byte writes acked at the given times in ms, each taking `latency` ms
+    fn samples(acks: &[f64], latency: f64) -> Vec<Sample> {
+        acks.iter()
+            .enumerate()
+            .map(|(sequence, ack)| Sample {
+                sequence: sequence as u64,
+                phase: 0,
+                submitted_ns: (ack - latency) * MS,
+                returned_ns: (ack - latency) * MS,
+                latency_ns: latency * MS,
+                bytes: 100,
+                failed: false,
+            })
+            .collect()
+    }
+
+    struct Case {
+        // Ack times in ms, 100 ms windows
+        acks: &'static [f64],
+        stop_ms: Option<u64>,
+        // (start ms, length ms, writes) of each window
+        windows: &'static [(f64, f64, u64)],
+    }
+
+    #[test]
+    fn buckets_acks_into_windows() {
+        let cases = [
+            // An empty run still gets a window
+            Case { acks: &[], stop_ms: None, windows: &[(0.0, 100.0, 0)] },
+            Case { acks: &[], stop_ms: Some(250), windows: &[(0.0, 100.0, 0), (100.0, 100.0, 0), (200.0, 50.0, 0)] },
+            // A run shorter than a window
+            Case { acks: &[10.0, 20.0, 50.0], stop_ms: None, windows: &[(0.0, 100.0, 3)] },
+            // A window takes acks from its start up to its end, and the last ack goes in the last window
+            Case { acks: &[99.0, 100.0, 199.0, 200.0], stop_ms: None, windows: &[(0.0, 100.0, 1), (100.0, 100.0, 3)] },
+            // Acks after the stop get a window of their own
+            Case {
+                acks: &[50.0, 140.0, 180.0],
+                stop_ms: Some(150),
+                windows: &[(0.0, 100.0, 1), (100.0, 50.0, 1), (150.0, 30.0, 1)],
+            },
+        ];
+        for case in cases {
+            let stop = case.stop_ms.map(Duration::from_millis);
+            let windows = timeline(&samples(case.acks, 1.0), Duration::from_millis(100), stop);
+            let actual: Vec<(f64, f64, u64)> = windows
+                .iter()
+                .map(|window| (window.start_ns / MS, window.duration_ns / MS, window.result.num_writes))
+                .collect();
+            assert_eq!(actual, case.windows, "acks {:?} stopped at {:?}", case.acks, stop);
+        }
+    }
+
+    #[test]
+    fn summarises_each_window_on_its_own() {
+        let mut samples = samples(&[10.0, 20.0, 30.0], 5.0);
+        samples.extend(self::samples(&[150.0], 40.0));
+        let windows = timeline(&samples, Duration::from_millis(100), None);
+        assert_eq!(windows.len(), 2);
+        let (first, second) = (&windows[0].result, &windows[1].result);
+        assert_eq!((first.num_writes, first.total_bytes, first.median / MS, first.max / MS), (3, 300, 5.0, 5.0));
+        assert_eq!((second.num_writes, second.total_bytes, second.median / MS, second.p99 / MS), (1, 100, 40.0, 40.0));
+    }
+}